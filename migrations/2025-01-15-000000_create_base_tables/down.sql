@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS user_role;
+DROP TABLE IF EXISTS sections;
+DROP TABLE IF EXISTS sessions;
+DROP TABLE IF EXISTS roles;
+DROP TABLE IF EXISTS users;
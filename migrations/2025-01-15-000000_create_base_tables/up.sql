@@ -0,0 +1,48 @@
+-- 上线时就有的表，已有的库里表已经存在，所以用 IF NOT EXISTS
+CREATE TABLE IF NOT EXISTS users (
+    id VARCHAR PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS roles (
+    id VARCHAR PRIMARY KEY,
+    created_by VARCHAR NOT NULL,
+    is_default BOOLEAN NOT NULL DEFAULT FALSE,
+    name TEXT NOT NULL,
+    picture_url TEXT NOT NULL,
+    voice_id TEXT NOT NULL,
+    audition_url TEXT NOT NULL,
+    prompt TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    session_id VARCHAR PRIMARY KEY,
+    user_id VARCHAR NOT NULL,
+    role_id VARCHAR NOT NULL,
+    title TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS sessions_user_id_idx ON sessions (user_id);
+
+CREATE TABLE IF NOT EXISTS sections (
+    section_id VARCHAR PRIMARY KEY,
+    session_id VARCHAR NOT NULL,
+    user_message TEXT NOT NULL,
+    assistant_message TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS sections_session_id_idx ON sections (session_id);
+
+CREATE TABLE IF NOT EXISTS user_role (
+    id VARCHAR PRIMARY KEY,
+    role_id VARCHAR NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
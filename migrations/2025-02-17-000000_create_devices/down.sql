@@ -0,0 +1,2 @@
+DROP TABLE device_pairings;
+DROP TABLE devices;
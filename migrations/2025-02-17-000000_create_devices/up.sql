@@ -0,0 +1,25 @@
+CREATE TABLE devices (
+    id VARCHAR PRIMARY KEY,
+    user_id VARCHAR REFERENCES users (id) ON DELETE SET NULL,
+    name TEXT NOT NULL DEFAULT '',
+    secret_hash TEXT,
+    paired_at TIMESTAMP,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX devices_user_id_idx ON devices (user_id);
+
+-- 配对码过期后可以复用，所以 code 不做唯一约束，发放时检查未过期的
+CREATE TABLE device_pairings (
+    id VARCHAR PRIMARY KEY,
+    code VARCHAR NOT NULL,
+    device_id VARCHAR NOT NULL REFERENCES devices (id) ON DELETE CASCADE,
+    pairing_secret VARCHAR NOT NULL,
+    confirmed_by VARCHAR REFERENCES users (id) ON DELETE CASCADE,
+    expires_at TIMESTAMP NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX device_pairings_code_idx ON device_pairings (code);
+CREATE INDEX device_pairings_device_id_idx ON device_pairings (device_id);
@@ -0,0 +1,8 @@
+ALTER TABLE roles
+    DROP COLUMN model,
+    DROP COLUMN temperature,
+    DROP COLUMN top_p,
+    DROP COLUMN max_tokens,
+    DROP COLUMN stop_sequences,
+    DROP COLUMN presence_penalty,
+    DROP COLUMN frequency_penalty;
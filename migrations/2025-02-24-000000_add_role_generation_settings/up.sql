@@ -0,0 +1,9 @@
+-- 为空时使用服务端默认值
+ALTER TABLE roles
+    ADD COLUMN model TEXT,
+    ADD COLUMN temperature REAL,
+    ADD COLUMN top_p REAL,
+    ADD COLUMN max_tokens INTEGER,
+    ADD COLUMN stop_sequences TEXT[],
+    ADD COLUMN presence_penalty REAL,
+    ADD COLUMN frequency_penalty REAL;
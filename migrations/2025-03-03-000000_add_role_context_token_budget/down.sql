@@ -0,0 +1 @@
+ALTER TABLE roles DROP COLUMN context_token_budget;
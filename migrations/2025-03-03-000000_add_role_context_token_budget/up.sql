@@ -0,0 +1 @@
+ALTER TABLE roles ADD COLUMN context_token_budget INTEGER;
@@ -0,0 +1,4 @@
+ALTER TABLE sessions
+    DROP COLUMN summary,
+    DROP COLUMN summarized_until,
+    DROP COLUMN summary_updated_at;
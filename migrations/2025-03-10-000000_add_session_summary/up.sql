@@ -0,0 +1,4 @@
+ALTER TABLE sessions
+    ADD COLUMN summary TEXT,
+    ADD COLUMN summarized_until TIMESTAMP,
+    ADD COLUMN summary_updated_at TIMESTAMP;
@@ -0,0 +1,12 @@
+CREATE TABLE user_memories (
+    id VARCHAR PRIMARY KEY,
+    user_id VARCHAR NOT NULL,
+    role_id VARCHAR NOT NULL,
+    content TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+-- 并发的两轮可能提取出同一条记忆，写入时靠这个索引去重
+CREATE UNIQUE INDEX user_memories_user_id_role_id_content_idx
+    ON user_memories (user_id, role_id, content);
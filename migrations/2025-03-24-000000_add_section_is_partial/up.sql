@@ -0,0 +1,2 @@
+-- 生成被打断时保存的半截回复
+ALTER TABLE sections ADD COLUMN is_partial BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,3 @@
+ALTER TABLE sections
+    DROP COLUMN input_audio_path,
+    DROP COLUMN output_audio_path;
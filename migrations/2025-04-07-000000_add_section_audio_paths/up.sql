@@ -0,0 +1,3 @@
+ALTER TABLE sections
+    ADD COLUMN input_audio_path VARCHAR,
+    ADD COLUMN output_audio_path VARCHAR;
@@ -0,0 +1,2 @@
+-- 会话开始时选出的记忆，同一个会话里保持不变
+ALTER TABLE sessions ADD COLUMN memories TEXT;
@@ -13,9 +13,9 @@ pub async fn test_llm() {
         .expect("Failed to create pool.");
 
     // 创建 AppState
-    let mut app_state = AppState::new(pool, OZ_SERVER_CONFIG.clone());
+    let app_state = AppState::new(pool, OZ_SERVER_CONFIG.clone());
 
-    let mut chat = chat::Chat::new(
+    let chat = chat::Chat::new(
         "1".to_string(),
         "4322f33b-3cac-49e4-8310-0584e9608220".to_string(),
         "cu6vho2mmejiu257gg80".to_string(),
@@ -34,7 +34,7 @@ pub async fn test_llm() {
     }
 }
 
-async fn test_mqtt() {
+pub async fn test_mqtt() {
     mqtt::publish_event(
         "test".to_string(),
         "4322f33b-3cac-49e4-8310-0584e9608220".to_string(),
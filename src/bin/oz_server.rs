@@ -1,11 +1,17 @@
 use axum::middleware;
-use axum::routing::post;
+use axum::routing::{delete, post, put};
 use axum::{extract::State, http, routing::get, Router};
 use chrono::Local;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::PgConnection;
 use env_logger::{Builder, WriteStyle};
-use oz_server::handlers::{auth, chat, echo_mage, get_roles, switch_role};
+use oz_server::constant::{
+    DEFAULT_METRICS_HOST, DEFAULT_METRICS_PORT, METRICS_HOST, METRICS_ON_MAIN_PORT, METRICS_PORT,
+};
+use oz_server::handlers::{
+    account, auth, chat, device, echo_mage, get_roles, memory, metrics, recording, switch_role,
+    update_role,
+};
 use oz_server::{config::OZ_SERVER_CONFIG, structures::AppState};
 use std::io::Write;
 use tower_http::cors::{Any, CorsLayer};
@@ -14,7 +20,7 @@ async fn health_check(State(_state): State<AppState>) -> &'static str {
     "OK"
 }
 
-async fn setup_router(app_state: AppState) -> Router {
+async fn setup_router(app_state: AppState, serve_metrics: bool) -> Router {
     let cors = CorsLayer::new()
         // 允许所有源
         .allow_origin(Any)
@@ -38,11 +44,11 @@ async fn setup_router(app_state: AppState) -> Router {
     // 允许携带认证信息
     // .allow_credentials(true);
 
-    Router::new()
+    let mut router = Router::new()
         .route("/api/roles", get(get_roles))
         .route("/api/role/switch", post(switch_role))
-        .route("/health", get(health_check))
-        //.route("/api/chat", post(chat::chat))
+        .route("/api/role/update", post(update_role))
+        .route("/api/chat", post(chat::chat))
         .route("/api/chat/history", get(chat::chat_history))
         .route(
             "/api/chat/session_history",
@@ -50,11 +56,46 @@ async fn setup_router(app_state: AppState) -> Router {
         )
         .route("/api/add_role", post(chat::add_role))
         .route("/api/ws/stream", get(echo_mage::ws_handler))
+        .route("/api/device/pairing/confirm", post(device::confirm_pairing))
+        .route("/api/devices", get(device::list_devices))
+        .route("/api/devices/{device_id}", delete(device::unpair_device))
+        .route("/api/memories", get(memory::list_memories))
+        .route(
+            "/api/memories/{memory_id}",
+            put(memory::update_memory).delete(memory::delete_memory),
+        )
+        .route(
+            "/api/sections/{section_id}/audio/{kind}",
+            get(recording::download_recording),
+        )
         .route_layer(middleware::from_fn(auth::auth))
+        // 以下路由不需要认证
+        .route("/health", get(health_check))
+        .route("/api/auth/register", post(account::register))
+        .route("/api/auth/login", post(account::login))
+        .route("/api/auth/refresh", post(account::refresh))
+        .route("/api/auth/logout", post(account::logout))
+        .route("/api/device/pairing/request", post(device::request_pairing))
+        .route("/api/device/pairing/poll", post(device::poll_pairing))
+        .route("/api/device/token", post(device::device_token));
+    // 显式配置 metrics_on_main_port 时指标和业务接口放在一起，不需要认证
+    if serve_metrics {
+        router = router.route("/metrics", get(metrics::metrics));
+    }
+
+    router
+        .route_layer(middleware::from_fn(metrics::track_requests))
         .layer(cors)
         .with_state(app_state)
 }
 
+/// 管理端口只提供指标，默认只监听本机
+fn setup_admin_router(app_state: AppState) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics::metrics))
+        .with_state(app_state)
+}
+
 async fn _main() {
     Builder::from_default_env()
         .format(|buf, record| {
@@ -78,11 +119,34 @@ async fn _main() {
         .build(manager)
         .expect("Failed to create pool.");
 
+    // 定期清理过期的录音
+    oz_server::services::recording::spawn_retention(pool.clone());
+
     // 创建 AppState
     let app_state = AppState::new(pool, OZ_SERVER_CONFIG.clone());
 
     // 设置路由
-    let app = setup_router(app_state).await;
+    let metrics_on_main_port = OZ_SERVER_CONFIG
+        .get::<bool>(METRICS_ON_MAIN_PORT)
+        .unwrap_or(false);
+    let app = setup_router(app_state.clone(), metrics_on_main_port).await;
+
+    if !metrics_on_main_port {
+        let host = OZ_SERVER_CONFIG
+            .get::<String>(METRICS_HOST)
+            .unwrap_or(DEFAULT_METRICS_HOST.to_string());
+        let port = OZ_SERVER_CONFIG
+            .get::<u16>(METRICS_PORT)
+            .unwrap_or(DEFAULT_METRICS_PORT);
+        let admin = setup_admin_router(app_state);
+        let listener = tokio::net::TcpListener::bind((host.as_str(), port))
+            .await
+            .unwrap();
+        println!("Metrics available on http://{}:{}/metrics", host, port);
+        tokio::spawn(async move {
+            axum::serve(listener, admin).await.unwrap();
+        });
+    }
 
     // 启动服务器
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
@@ -0,0 +1,9 @@
+use oz_server::json::ws::protocol_schema;
+
+// 导出语音 WebSocket 协议的 JSON Schema
+fn main() {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&protocol_schema()).unwrap()
+    );
+}
@@ -1,7 +1,13 @@
-use config::{Config, File};
+use config::{Config, Environment, File};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+// 环境变量前缀，例如 OZ_ASR_CONFIG__TOKEN 对应 asr_config.token
+const ENV_PREFIX: &str = "OZ";
+const ENV_SEPARATOR: &str = "__";
+
+/// 凭据 (app_id、token) 只能来自配置文件或环境变量，代码中没有默认值
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct AsrConfig {
     pub app_id: String,
     pub token: String,
@@ -12,7 +18,8 @@ pub struct AsrConfig {
     pub sample_rate: u32,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
 pub struct TtsConfig {
     pub app_id: String,
     pub token: String,
@@ -24,10 +31,10 @@ pub struct TtsConfig {
 impl Default for AsrConfig {
     fn default() -> Self {
         Self {
-            app_id: "9335493412".to_string(),
-            token: "m6oeKE_9QbYQg4EiWunryPUErK81rMRF".to_string(),
+            app_id: String::new(),
+            token: String::new(),
             cluster: "volcengine_streaming_common".to_string(),
-            audio_format: "mp3".to_string(),
+            audio_format: "raw".to_string(),
             codec: "raw".to_string(),
             workflow: "audio_in,resample,partition,vad,fe,decode".to_string(),
             sample_rate: 16000,
@@ -35,65 +42,50 @@ impl Default for AsrConfig {
     }
 }
 
-impl AsrConfig {
-    pub fn to_toolkit_config(&self) -> llm_audio_toolkit::asr::volc::VolcanoConfig {
-        llm_audio_toolkit::asr::volc::VolcanoConfig {
-            app_id: self.app_id.clone(),
-            token: self.token.clone(),
-            cluster: self.cluster.clone(),
-            audio_format: self.audio_format.clone(),
-            codec: self.codec.clone(),
-            workflow: self.workflow.clone(),
-            sample_rate: self.sample_rate,
-        }
-    }
-}
-
-
 impl Default for TtsConfig {
     fn default() -> Self {
         Self {
-            app_id: "9335493412".to_string(),
-            token: "m6oeKE_9QbYQg4EiWunryPUErK81rMRF".to_string(),
+            app_id: String::new(),
+            token: String::new(),
             cluster: "volcano_icl".to_string(),
             voice_type: "S_TfBFm6r41".to_string(),
-            enc_format: "mp3".to_string(),
+            enc_format: "pcm".to_string(),
         }
     }
 }
 
-impl TtsConfig {
-    pub fn to_toolkit_config(&self) -> llm_audio_toolkit::tts::volc::VolcConfig {
-        llm_audio_toolkit::tts::volc::VolcConfig {
-            app_id: self.app_id.clone(),
-            token: self.token.clone(),
-            cluster: self.cluster.clone(),
-            voice_type: self.voice_type.clone(),
-            enc_format: self.enc_format.clone(),
-        }
-    }
-}
-
-#[derive(Debug, Deserialize, Serialize)]
+/// database_url 必须配置，缺了启动时直接报错；
+/// openai_api_key 只有使用 openai provider 时才需要，在创建 provider 时检查
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GlobalConfig {
-    pub openai_api_key: String,
+    pub openai_api_key: Option<String>,
     pub database_url: String,
 
+    #[serde(default)]
     pub asr_config: AsrConfig,
+    #[serde(default)]
     pub tts_config: TtsConfig,
 }
 
 impl GlobalConfig {
+    /// 先读 .config.json，再用 OZ_ 前缀的环境变量覆盖
     pub fn load() -> Self {
         let config = Config::builder()
-            .add_source(File::with_name(".config.json"))
+            .add_source(File::with_name(".config.json").required(false))
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("_")
+                    .separator(ENV_SEPARATOR),
+            )
             .build()
-            .unwrap();
-        Self {
-            openai_api_key: config.get::<String>("openai_api_key").unwrap(),
-            database_url: config.get::<String>("database_url").unwrap(),
-            asr_config: AsrConfig::default(),
-            tts_config: TtsConfig::default(),
+            .and_then(|config| config.try_deserialize::<GlobalConfig>())
+            .expect("Failed to load global config");
+        if config.asr_config.token.is_empty() {
+            log::warn!("asr_config.token is not configured, ASR will be unavailable");
+        }
+        if config.tts_config.token.is_empty() {
+            log::warn!("tts_config.token is not configured, TTS will be unavailable");
         }
+        config
     }
 }
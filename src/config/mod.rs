@@ -1,6 +1,7 @@
 pub mod global_cfg;
 use config::{Config, File};
 use global_cfg::GlobalConfig;
+use std::sync::Arc;
 
 pub fn load_oz_server_config() -> Config {
     Config::builder()
@@ -9,9 +10,7 @@ pub fn load_oz_server_config() -> Config {
         .unwrap()
 }
 
-
 lazy_static::lazy_static! {
     pub static ref OZ_SERVER_CONFIG: Config = load_oz_server_config();
-    pub static ref GLOBAL_CONFIG: GlobalConfig = GlobalConfig::load();
+    pub static ref GLOBAL_CONFIG: Arc<GlobalConfig> = Arc::new(GlobalConfig::load());
 }
-
@@ -1,11 +1,85 @@
 pub const API_BASE_URL: &str = "https://api.deepseek.com";
 pub const OPEN_API_KEY: &str = "open_api_key";
-pub const SECTION_LIMIT: i64 = 2;
+pub const DEFAULT_LLM_MODEL: &str = "deepseek-chat";
+pub const DEFAULT_OLLAMA_BASE_URL: &str = "http://localhost:11434";
+pub const DEFAULT_OLLAMA_MODEL: &str = "qwen2.5";
+
+pub const LLM_CONFIG: &str = "llm";
+pub const LLM_PROVIDER_OPENAI: &str = "openai";
+pub const LLM_PROVIDER_OLLAMA: &str = "ollama";
+pub const LLM_PROVIDER_MOCK: &str = "mock";
+
+pub const VOICE_CONFIG: &str = "voice";
+pub const VOICE_PROVIDER_VOLCANO: &str = "volcano";
+pub const VOICE_PROVIDER_MOCK: &str = "mock";
 
 pub const MAX_TOKENS: u32 = 512;
+pub const MAX_ROLE_MAX_TOKENS: u32 = 8192;
 
 pub const MQTT_MSG_SOURCE_DEVICE: &str = "0";
 pub const MQTT_MSG_SOURCE_USER: &str = "1";
 
 
 pub const PROMPT_GENERATE_SESSION_TITLE: &str = "请根据对话内容生成一个会话标题，标题不超过10个字";
+// 标题只有十来个字，不需要角色配置的长度
+pub const TITLE_MAX_TOKENS: u32 = 32;
+
+pub const JWT_SECRET: &str = "jwt_secret";
+pub const JWT_ACCESS_TOKEN_TTL: &str = "jwt_access_token_ttl";
+pub const AUTH_ALLOW_LEGACY_HEADER: &str = "auth_allow_legacy_header";
+pub const DEFAULT_ACCESS_TOKEN_TTL_SECS: i64 = 3600;
+pub const JWT_REFRESH_TOKEN_TTL: &str = "jwt_refresh_token_ttl";
+pub const DEFAULT_REFRESH_TOKEN_TTL_SECS: i64 = 30 * 24 * 3600;
+
+pub const PAIRING_CODE_TTL_SECS: u64 = 600;
+pub const PAIRING_STATUS_PENDING: &str = "pending";
+pub const PAIRING_STATUS_PAIRED: &str = "paired";
+pub const PAIRING_CONFIRM_MAX_FAILURES: u32 = 5;
+pub const PAIRING_CONFIRM_LOCKOUT_SECS: u64 = 900;
+
+pub const CONTEXT_CONFIG: &str = "context";
+pub const DEFAULT_CONTEXT_TOKENS: usize = 3000;
+pub const DEFAULT_HISTORY_FETCH_LIMIT: i64 = 50;
+pub const MIN_CONTEXT_TOKENS: u32 = 256;
+pub const MAX_CONTEXT_TOKENS: u32 = 128_000;
+
+pub const SUMMARY_BATCH_SIZE: i64 = 20;
+pub const SUMMARY_MAX_TOKENS: u32 = 512;
+pub const PROMPT_SUMMARIZE_CONVERSATION: &str = "你会收到一段对话的已有摘要和之后新的对话内容。请把它们合并成一份新的摘要，保留用户提到的关键信息、偏好和尚未结束的话题，不超过300字，只输出摘要内容。";
+pub const PROMPT_SUMMARY_PREFIX: &str = "以下是你和用户之前对话的摘要：";
+
+pub const MEMORY_EXTRACTION: &str = "memory_extraction";
+pub const MEMORY_PROMPT_LIMIT: usize = 10;
+pub const MAX_MEMORIES_PER_ROLE: i64 = 200;
+pub const MAX_MEMORY_LEN: usize = 200;
+pub const PROMPT_EXTRACT_MEMORY: &str = "从下面这轮对话中提取关于用户本人、值得长期记住的事实，例如名字、家人、宠物、喜好、重要的日子。不要重复已知信息。只输出一个 JSON 字符串数组，每条不超过30字，没有就输出 []。";
+pub const PROMPT_MEMORY_PREFIX: &str = "你记得关于用户的这些信息：";
+
+pub const PERSIST_PARTIAL_REPLY: &str = "persist_partial_reply";
+
+pub const DEFAULT_ROLE_ID: &str = "default_role";
+pub const SSE_EVENT_CHUNK: &str = "chunk";
+pub const SSE_EVENT_ERROR: &str = "error";
+
+pub const WS_IDLE_TIMEOUT_SECS: &str = "ws_idle_timeout_secs";
+pub const DEFAULT_WS_IDLE_TIMEOUT_SECS: u64 = 120;
+pub const TTS_LOOKAHEAD: &str = "tts_lookahead";
+pub const DEFAULT_TTS_LOOKAHEAD: usize = 2;
+
+pub const METRICS_PORT: &str = "metrics_port";
+pub const METRICS_HOST: &str = "metrics_host";
+pub const METRICS_ON_MAIN_PORT: &str = "metrics_on_main_port";
+pub const DEFAULT_METRICS_HOST: &str = "127.0.0.1";
+pub const DEFAULT_METRICS_PORT: u16 = 9100;
+
+pub const RECORDING_CONFIG: &str = "recording";
+pub const RECORDING_STORAGE_LOCAL: &str = "local";
+pub const DEFAULT_RECORDING_DIR: &str = "recordings";
+pub const RECORDING_RETENTION_CHECK_SECS: u64 = 3600;
+pub const RECORDING_PURGE_BATCH_SIZE: i64 = 500;
+pub const OPUS_CONFIG: &str = "opus";
+pub const DEFAULT_OPUS_FRAME_MS: u32 = 20;
+pub const VAD_CONFIG: &str = "vad";
+pub const DEFAULT_VAD_ENERGY_THRESHOLD_DB: f32 = -40.0;
+pub const DEFAULT_VAD_SILENCE_MS: u64 = 800;
+pub const DEFAULT_VAD_MIN_SPEECH_MS: u64 = 300;
@@ -0,0 +1,258 @@
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::{Json, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use diesel::PgConnection;
+use log::error;
+
+use crate::handlers::auth::unauthorized;
+use crate::models::{
+    refresh_token::RefreshToken,
+    schema::{refresh_tokens, users},
+    user::User,
+};
+use crate::structures::user::{
+    LoginRequest, RefreshTokenRequest, RegisterRequest, TokenPayload, TokenResponse,
+};
+use crate::structures::{AppState, CommonResponse};
+use crate::utils::{self, jwt};
+
+const MIN_USERNAME_LEN: usize = 3;
+const MAX_USERNAME_LEN: usize = 32;
+const MIN_PASSWORD_LEN: usize = 8;
+const MAX_PASSWORD_LEN: usize = 72; // bcrypt 只使用前 72 个字节
+
+lazy_static::lazy_static! {
+    /// 用户不存在时也校验一次密码，让响应时间和用户存在时一样
+    static ref DUMMY_PASSWORD_HASH: String = bcrypt::hash("oz-dummy-password", bcrypt::DEFAULT_COST)
+        .expect("Failed to hash dummy password");
+}
+
+pub fn internal_error<E: std::fmt::Display>(e: E) -> Response {
+    error!("account handler error: {}", e);
+    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+}
+
+fn validate_credentials(username: &str, password: &str) -> Result<(), &'static str> {
+    if username.chars().count() < MIN_USERNAME_LEN || username.chars().count() > MAX_USERNAME_LEN {
+        return Err("Username must be between 3 and 32 characters");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+    {
+        return Err("Username contains invalid characters");
+    }
+    if password.len() < MIN_PASSWORD_LEN || password.len() > MAX_PASSWORD_LEN {
+        return Err("Password must be between 8 and 72 bytes");
+    }
+    Ok(())
+}
+
+pub async fn hash_password(password: String) -> anyhow::Result<String> {
+    let hash =
+        tokio::task::spawn_blocking(move || bcrypt::hash(password, bcrypt::DEFAULT_COST)).await??;
+    Ok(hash)
+}
+
+pub async fn verify_password(password: String, hash: String) -> anyhow::Result<bool> {
+    let valid = tokio::task::spawn_blocking(move || bcrypt::verify(password, &hash)).await??;
+    Ok(valid)
+}
+
+/// 签发一对 access/refresh token，refresh token 的 jti 落库用于轮换和吊销
+/// device_id 只由设备接口 (/api/device/token、配对轮询) 传入，账号登录签发的 token 不绑定设备
+pub fn issue_token_pair(
+    conn: &mut PgConnection,
+    user_id: &str,
+    device_id: Option<&str>,
+) -> anyhow::Result<TokenPayload> {
+    let access_token = jwt::issue_access_token(user_id, device_id)?;
+    let (refresh_token, claims) = jwt::issue_refresh_token(user_id, device_id)?;
+
+    let now = SystemTime::now();
+    let row = RefreshToken {
+        id: claims.jti,
+        user_id: user_id.to_string(),
+        device_id: device_id.map(|d| d.to_string()),
+        revoked: false,
+        expires_at: SystemTime::UNIX_EPOCH + Duration::from_secs(claims.exp.max(0) as u64),
+        created_at: now,
+        updated_at: now,
+    };
+    diesel::insert_into(refresh_tokens::table)
+        .values(&row)
+        .execute(conn)?;
+
+    Ok(TokenPayload {
+        user_id: user_id.to_string(),
+        access_token,
+        refresh_token,
+        token_type: "Bearer".to_string(),
+        expires_in: jwt::access_token_ttl(),
+    })
+}
+
+pub fn revoke_all_refresh_tokens(conn: &mut PgConnection, user_id: &str) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::user_id.eq(user_id))
+            .filter(refresh_tokens::revoked.eq(false)),
+    )
+    .set((
+        refresh_tokens::revoked.eq(true),
+        refresh_tokens::updated_at.eq(SystemTime::now()),
+    ))
+    .execute(conn)
+}
+
+pub async fn register(
+    State(state): State<AppState>,
+    Json(payload): Json<RegisterRequest>,
+) -> Result<Json<TokenResponse>, Response> {
+    let username = payload.username.trim().to_string();
+    if let Err(msg) = validate_credentials(&username, &payload.password) {
+        return Ok(Json(TokenResponse::error(msg)));
+    }
+
+    let password_hash = hash_password(payload.password)
+        .await
+        .map_err(internal_error)?;
+
+    let conn = &mut state.db_pool.get().map_err(internal_error)?;
+
+    let now = SystemTime::now();
+    let user = User {
+        id: utils::gen_new_id(),
+        username,
+        password_hash,
+        created_at: now,
+        updated_at: now,
+    };
+    // 用户名的唯一约束兜住并发注册
+    match diesel::insert_into(users::table)
+        .values(&user)
+        .execute(conn)
+    {
+        Ok(_) => {}
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            return Ok(Json(TokenResponse::error("Username already taken")));
+        }
+        Err(e) => return Err(internal_error(e)),
+    }
+
+    let tokens = issue_token_pair(conn, &user.id, None).map_err(internal_error)?;
+    Ok(Json(TokenResponse::success(tokens)))
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    Json(payload): Json<LoginRequest>,
+) -> Result<Json<TokenResponse>, Response> {
+    let user = {
+        let conn = &mut state.db_pool.get().map_err(internal_error)?;
+        users::table
+            .filter(users::username.eq(payload.username.trim()))
+            .select(User::as_select())
+            .first(conn)
+            .optional()
+            .map_err(internal_error)?
+    };
+
+    let password_hash = match &user {
+        Some(user) => user.password_hash.clone(),
+        None => DUMMY_PASSWORD_HASH.clone(),
+    };
+    let valid = verify_password(payload.password, password_hash)
+        .await
+        .map_err(internal_error)?;
+    let user = match user {
+        Some(user) if valid => user,
+        _ => {
+            return Err(unauthorized(
+                "invalid_credentials",
+                "Invalid username or password",
+            ))
+        }
+    };
+
+    let conn = &mut state.db_pool.get().map_err(internal_error)?;
+    let tokens = issue_token_pair(conn, &user.id, None).map_err(internal_error)?;
+    Ok(Json(TokenResponse::success(tokens)))
+}
+
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshTokenRequest>,
+) -> Result<Json<TokenResponse>, Response> {
+    let claims = jwt::verify_refresh_token(&payload.refresh_token)
+        .map_err(|e| unauthorized(e.reason(), &e.to_string()))?;
+
+    let conn = &mut state.db_pool.get().map_err(internal_error)?;
+
+    let stored = refresh_tokens::table
+        .find(claims.jti.as_str())
+        .select(RefreshToken::as_select())
+        .first(conn)
+        .optional()
+        .map_err(internal_error)?;
+    let stored = match stored {
+        Some(stored) => stored,
+        None => return Err(unauthorized("token_invalid", "Unknown refresh token")),
+    };
+
+    // 只有仍未吊销的 token 能被轮换，并发的重复刷新只有一个能成功
+    let rotated = diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::id.eq(&stored.id))
+            .filter(refresh_tokens::revoked.eq(false)),
+    )
+    .set((
+        refresh_tokens::revoked.eq(true),
+        refresh_tokens::updated_at.eq(SystemTime::now()),
+    ))
+    .execute(conn)
+    .map_err(internal_error)?;
+
+    if rotated == 0 {
+        // 已吊销的 refresh token 被再次使用，视为泄露，吊销该用户全部 refresh token
+        error!("refresh token reuse detected for user {}", stored.user_id);
+        revoke_all_refresh_tokens(conn, &stored.user_id).map_err(internal_error)?;
+        return Err(unauthorized(
+            "token_revoked",
+            "Refresh token has been revoked",
+        ));
+    }
+
+    let tokens = issue_token_pair(conn, &stored.user_id, stored.device_id.as_deref())
+        .map_err(internal_error)?;
+    Ok(Json(TokenResponse::success(tokens)))
+}
+
+pub async fn logout(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshTokenRequest>,
+) -> Result<Json<CommonResponse>, Response> {
+    let claims = match jwt::verify_refresh_token(&payload.refresh_token) {
+        Ok(claims) => claims,
+        // 过期的 token 已经不能再使用，直接视为登出成功
+        Err(jwt::TokenError::Expired) => return Ok(Json(CommonResponse::success())),
+        Err(e) => return Err(unauthorized(e.reason(), &e.to_string())),
+    };
+
+    let conn = &mut state.db_pool.get().map_err(internal_error)?;
+    diesel::update(refresh_tokens::table.filter(refresh_tokens::id.eq(&claims.jti)))
+        .set((
+            refresh_tokens::revoked.eq(true),
+            refresh_tokens::updated_at.eq(SystemTime::now()),
+        ))
+        .execute(conn)
+        .map_err(internal_error)?;
+
+    Ok(Json(CommonResponse::success()))
+}
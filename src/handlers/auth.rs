@@ -1,50 +1,90 @@
 use axum::{
-    extract::{Extension, Request},
-    http::StatusCode,
+    extract::Request,
+    http::{header, HeaderMap, StatusCode},
     middleware::{Next},
-    response::Response,
+    response::{IntoResponse, Response},
+    Json,
 };
+use log::debug;
 
-use crate::structures::user::CurrentUser;
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::AUTH_ALLOW_LEGACY_HEADER;
+use crate::structures::user::{AuthErrorResponse, CurrentUser};
+use crate::utils::jwt::{self, TokenError};
 
 const AUTH_HEADER_NAME: &str = "x-oz-user-id";
 const AUTH_HEADER_DEV_ID: &str = "x-oz-dev-id";
+const BEARER_PREFIX: &str = "Bearer ";
 
-pub async fn auth(mut req: Request, next: Next) -> Result<Response, StatusCode> {
-    let auth_header = req
-        .headers()
-        .get(AUTH_HEADER_NAME)
-        .or_else(|| req.headers().get(AUTH_HEADER_DEV_ID))
-        .and_then(|header| header.to_str().ok());
-
-    let auth_header = if let Some(auth_header) = auth_header {
-        auth_header
+pub async fn auth(mut req: Request, next: Next) -> Response {
+    let current_user = if let Some(token) = bearer_token(req.headers()) {
+        match authorize_current_user(token).await {
+            Ok(current_user) => current_user,
+            Err(e) => {
+                debug!("rejecting access token: {}", e);
+                return unauthorized(e.reason(), &e.to_string());
+            }
+        }
+    } else if legacy_header_enabled() {
+        match legacy_current_user(req.headers()) {
+            Some(current_user) => current_user,
+            None => return unauthorized("token_missing", "Missing user id header"),
+        }
     } else {
-        return Err(StatusCode::UNAUTHORIZED);
+        return unauthorized("token_missing", "Missing bearer token");
     };
 
-    if let Some(current_user) = authorize_current_user(auth_header).await {
-        // insert the current user into a request extension so the handler can
-        // extract it
-        req.extensions_mut().insert(current_user);
-        Ok(next.run(req).await)
-    } else {
-        Err(StatusCode::UNAUTHORIZED)
-    }
+    // insert the current user into a request extension so the handler can
+    // extract it
+    req.extensions_mut().insert(current_user);
+    next.run(req).await
+}
+
+pub fn unauthorized(reason: &str, msg: &str) -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(AuthErrorResponse::new(reason, msg)),
+    )
+        .into_response()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+/// 仅用于本地开发：直接信任 x-oz-user-id / x-oz-dev-id 头
+fn legacy_header_enabled() -> bool {
+    OZ_SERVER_CONFIG
+        .get::<bool>(AUTH_ALLOW_LEGACY_HEADER)
+        .unwrap_or(false)
 }
 
-async fn authorize_current_user(auth_token: &str) -> Option<CurrentUser> {
-    if auth_token.is_empty() {
-        return None;
-    }
+fn legacy_current_user(headers: &HeaderMap) -> Option<CurrentUser> {
+    let user_id = headers
+        .get(AUTH_HEADER_NAME)
+        .or_else(|| headers.get(AUTH_HEADER_DEV_ID))
+        .and_then(|header| header.to_str().ok())
+        .filter(|value| !value.is_empty())?;
+    let device_id = headers
+        .get(AUTH_HEADER_DEV_ID)
+        .and_then(|header| header.to_str().ok())
+        .map(|value| value.to_string());
+
     Some(CurrentUser {
-        user_id: auth_token.to_string(),
+        user_id: user_id.to_string(),
+        device_id,
     })
 }
 
-async fn test_auth_handler(
-    // extract the current user, set by the middleware
-    Extension(current_user): Extension<CurrentUser>,
-) {
-    println!("current_user: {}", current_user.user_id);
+async fn authorize_current_user(auth_token: &str) -> Result<CurrentUser, TokenError> {
+    let claims = jwt::verify_access_token(auth_token)?;
+    Ok(CurrentUser {
+        user_id: claims.sub,
+        device_id: claims.dev,
+    })
 }
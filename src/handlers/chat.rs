@@ -4,59 +4,94 @@ use crate::json::chat_history_response::{ChatHistoryResponse, History, Payload};
 use crate::json::chat_session_history::{
     ChatSessionHistoryRequest, ChatSessionHistoryResponse, History as ChatSessionHistoryHistory,
 };
-use crate::json::openai_response::OpenAIResponse;
 use crate::json::role::AddRoleRequest;
 use crate::models::role;
 use crate::models::schema;
 use crate::models::schema::roles::dsl;
 use crate::models::section::Section;
 use crate::models::session::Session;
+use crate::handlers::role::resolve_role_id;
+use crate::services::context::{self, ContextConfig, Turn};
+use crate::services::memory;
+use crate::services::metrics::{TurnStage, TurnTimings};
+use crate::services::recording::TurnRecording;
+use crate::services::summary;
+use crate::services::llm::{GenerationParams, LlmMessage, LlmProvider, LlmRequest, LLM_PROVIDER};
 use crate::structures::app_error::AppError;
 use crate::structures::app_state::AppState;
+use crate::structures::CommonResponse;
+use crate::structures::user::CurrentUser;
 use crate::utils;
 use crate::utils::mqtt;
 use anyhow::Result;
-use async_openai::types::ChatCompletionRequestMessage;
-use async_openai::{
-    config,
-    types::{
-        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestSystemMessageArgs,
-        ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
-    },
-    Client,
-};
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::{ExpressionMethods, PgConnection};
 use diesel::QueryDsl;
 use diesel::RunQueryDsl;
 use diesel::SelectableHelper;
+use diesel::{OptionalExtension, QueryResult};
 use log::debug;
+use std::sync::Arc;
 use std::time::SystemTime;
 
 use axum::{
     extract::{Query, State},
     http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    Json,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension, Json,
 };
 
 use regex::Regex;
+use serde::Serialize;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_util::sync::CancellationToken;
 use futures_util::stream::StreamExt;
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ChatResponse {
+    /// 新会话在第一句回复时才有 id，客户端需要记下来用于后续对话
+    pub session_id: String,
+    /// 本次回复中的分句序号，从 0 开始
+    pub index: usize,
     pub split_text: String,
     pub is_end: bool,
 }
 
-use crate::json::chat::ChatHistoryRequest;
+use crate::json::chat::{ChatHistoryRequest, ChatRequest};
 
 pub struct Chat {
     user_id: String,
     session_id: String,
     role_id: String,
     db_pool: Pool<ConnectionManager<PgConnection>>,
+    llm: Arc<dyn LlmProvider>,
+    cancel: CancellationToken,
+    recording: Option<TurnRecording>,
+    timings: Option<Arc<TurnTimings>>,
+}
+
+/// 生成被中断时是否保存已经生成的部分回复
+fn persist_partial_reply() -> bool {
+    OZ_SERVER_CONFIG
+        .get::<bool>(PERSIST_PARTIAL_REPLY)
+        .unwrap_or(true)
+}
+
+/// 只查找属于该用户的会话，客户端传来的 session_id 不可信
+pub fn find_user_session(
+    conn: &mut PgConnection,
+    user_id: &str,
+    session_id: &str,
+) -> QueryResult<Option<Session>> {
+    schema::sessions::table
+        .filter(schema::sessions::session_id.eq(session_id))
+        .filter(schema::sessions::user_id.eq(user_id))
+        .select(Session::as_select())
+        .first(conn)
+        .optional()
 }
 
 impl Chat {
@@ -71,10 +106,44 @@ impl Chat {
             session_id,
             role_id,
             db_pool,
+            llm: LLM_PROVIDER.clone(),
+            cancel: CancellationToken::new(),
+            recording: None,
+            timings: None,
+        }
+    }
+
+    /// 由调用方控制中断，token 被取消后停止生成，不再推送后续分句
+    pub fn with_cancel_token(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// 语音对话的录音，section 使用录音的 id 并记录录音的 key
+    pub fn with_recording(mut self, recording: TurnRecording) -> Self {
+        self.recording = Some(recording);
+        self
+    }
+
+    /// 记录第一个 token 和第一句话的时间
+    pub fn with_timings(mut self, timings: Arc<TurnTimings>) -> Self {
+        self.timings = Some(timings);
+        self
+    }
+
+    fn mark(&self, stage: TurnStage) {
+        if let Some(timings) = &self.timings {
+            timings.mark(stage);
         }
     }
 
-    async fn finish_insert_session(&self) -> Result<String> {
+    /// 替换默认的 LLM provider，例如在测试中使用 mock
+    pub fn with_llm_provider(mut self, llm: Arc<dyn LlmProvider>) -> Self {
+        self.llm = llm;
+        self
+    }
+
+    async fn finish_insert_session(&self, memories: &[String]) -> Result<String> {
         println!("finish_insert_session {:?}", self.user_id);
         let session = Session {
             session_id: self.session_id.clone(),
@@ -82,6 +151,11 @@ impl Chat {
             role_id: self.role_id.clone(),
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            summary: None,
+            summarized_until: None,
+            summary_updated_at: None,
+            last_turn_metrics: None,
+            memories: memory::to_session_memories(memories),
         };
 
         diesel::insert_into(schema::sessions::table)
@@ -94,67 +168,72 @@ impl Chat {
         &self,
         message: String,
         assistant_message: String,
+        is_partial: bool,
     ) -> Result<String> {
         let section = Section {
-            section_id: utils::gen_new_id(),
+            section_id: match &self.recording {
+                Some(recording) => recording.section_id.clone(),
+                None => utils::gen_new_id(),
+            },
             session_id: self.session_id.clone(),
             user_message: message,
-            assistant_message: assistant_message,
+            assistant_message,
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            is_partial,
+            input_audio_path: self.recording.as_ref().map(|r| r.input_key.clone()),
+            output_audio_path: self.recording.as_ref().map(|r| r.output_key.clone()),
         };
 
         diesel::insert_into(schema::sections::table)
             .values(&section)
             .execute(&mut self.db_pool.get()?)?;
+        if let Some(recording) = &self.recording {
+            recording.save_input();
+        }
         Ok("".to_string())
     }
 
-    async fn fill_message_by_session_id(
+    /// 读取会话最近的若干轮对话，按从旧到新排列。已经被摘要覆盖的部分不再读取
+    async fn load_history(
         &self,
-        messages: &mut Vec<ChatCompletionRequestMessage>,
         session_id: String,
-    ) -> Result<()> {
-        let sections = schema::sections::table
+        limit: i64,
+        after: Option<SystemTime>,
+    ) -> Result<Vec<Turn>> {
+        let mut query = schema::sections::table
             .filter(schema::sections::session_id.eq(session_id))
+            .into_boxed();
+        if let Some(after) = after {
+            query = query.filter(schema::sections::created_at.gt(after));
+        }
+        let sections = query
             .order(schema::sections::created_at.desc())
-            .limit(SECTION_LIMIT)
+            .limit(limit)
             .select(Section::as_select())
             .load(&mut self.db_pool.get()?)?;
 
-        for section in sections.iter().rev() {
-            messages.push(
-                ChatCompletionRequestUserMessageArgs::default()
-                    .content(section.user_message.clone())
-                    .build()?
-                    .into(),
-            );
-
-            messages.push(
-                ChatCompletionRequestAssistantMessageArgs::default()
-                    .content(section.assistant_message.clone())
-                    .build()?
-                    .into(),
-            );
-        }
-
-        Ok(())
+        Ok(sections
+            .into_iter()
+            .rev()
+            .map(|section| Turn {
+                user: section.user_message,
+                assistant: section.assistant_message,
+            })
+            .collect())
     }
 
     async fn check_need_new_session(&self) -> Result<bool> {
-        let session = schema::sessions::table
-            .filter(schema::sessions::session_id.eq(self.session_id.clone()))
-            .select(Session::as_select())
-            .load(&mut self.db_pool.get()?)?;
-
-        if session.is_empty() {
-            return Ok(true);
-        }
-
-        let last_session = session.last().ok_or(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "Session not found",
-        )))?;
+        // 别人的会话和不存在的会话一样处理，开一个新会话
+        let session = find_user_session(
+            &mut *self.db_pool.get()?,
+            &self.user_id,
+            &self.session_id,
+        )?;
+        let last_session = match session {
+            Some(session) => session,
+            None => return Ok(true),
+        };
         if last_session.role_id != self.role_id {
             return Ok(true);
         }
@@ -162,47 +241,22 @@ impl Chat {
         Ok(false)
     }
 
-    fn generate_open_client(&self) -> Client<config::OpenAIConfig> {
-        let config = config::OpenAIConfig::new()
-            .with_api_base(API_BASE_URL)
-            .with_api_key(OZ_SERVER_CONFIG.get::<String>(OPEN_API_KEY).unwrap());
-
-        Client::with_config(config)
-    }
-
     async fn generate_session_title(
         &mut self,
-        mut messages: Vec<ChatCompletionRequestMessage>,
+        mut messages: Vec<LlmMessage>,
         device_message: String,
+        params: GenerationParams,
     ) -> Result<String> {
-        let client = self.generate_open_client();
-
-        messages.push(
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(PROMPT_GENERATE_SESSION_TITLE.to_string())
-                .build()?
-                .into(),
-        );
+        messages.push(LlmMessage::user(PROMPT_GENERATE_SESSION_TITLE.to_string()));
+        messages.push(LlmMessage::assistant(device_message));
 
-        messages.push(
-            ChatCompletionRequestAssistantMessageArgs::default()
-                .content(device_message)
-                .build()?
-                .into(),
-        );
-
-        let request = CreateChatCompletionRequestArgs::default()
-            .max_tokens(MAX_TOKENS)
-            .model("deepseek-chat")
-            .messages(messages)
-            .build()?;
-
-        let response = client.chat().create(request).await?;
-
-        let openai_response =
-            serde_json::from_str::<OpenAIResponse>(&serde_json::to_string(&response)?)?;
+        let params = GenerationParams {
+            max_tokens: Some(TITLE_MAX_TOKENS),
+            ..params
+        };
+        let request = LlmRequest { messages, params };
 
-        let title = openai_response.choices[0].message.content.clone();
+        let title = self.llm.complete(request).await?;
 
         Ok(title)
     }
@@ -218,11 +272,20 @@ impl Chat {
         Ok(())
     }
 
+    fn chat_response(&self, index: usize, split_text: String, is_end: bool) -> ChatResponse {
+        ChatResponse {
+            session_id: self.session_id.clone(),
+            index,
+            split_text,
+            is_end,
+        }
+    }
+
     async fn deal_message(mut self, message: String, sender: Sender<ChatResponse>) -> Result<(), anyhow::Error> {
         println!("recv message: {}", message);
 
         let mut is_first = false;
-        if self.session_id == "" {
+        if self.session_id.is_empty() {
             self.session_id = utils::gen_new_id();
             is_first = true;
         } else {
@@ -243,103 +306,186 @@ impl Chat {
             "Role not found",
         )))?;
 
-        let client = self.generate_open_client();
+        let context_config = ContextConfig::load();
+        let session_summary = if is_first {
+            None
+        } else {
+            summary::load_summary(&mut *self.db_pool.get()?, &self.session_id).unwrap_or(None)
+        };
+        let history = if is_first {
+            Vec::new()
+        } else {
+            self.load_history(
+                self.session_id.clone(),
+                context_config.history_fetch_limit,
+                session_summary.as_ref().map(|s| s.summarized_until),
+            )
+            .await
+            .unwrap_or_default()
+        };
 
-        let mut messages = Vec::new();
-        messages.push(
-            ChatCompletionRequestSystemMessageArgs::default()
-                .content(role.prompt.clone())
-                .build()?
-                .into(),
+        let budget = role
+            .context_token_budget
+            .map(|budget| budget as usize)
+            .unwrap_or(context_config.max_tokens);
+        // 长期记忆按用户+角色隔离，会话开始时挑选和第一句最相关的几条，之后整个会话沿用
+        let memories = if is_first {
+            memory::load_relevant(
+                &mut *self.db_pool.get()?,
+                &self.user_id,
+                &self.role_id,
+                &message,
+            )
+            .map(|memories| memories.into_iter().map(|m| m.content).collect())
+            .unwrap_or_default()
+        } else {
+            memory::load_session_memories(&mut *self.db_pool.get()?, &self.session_id)
+                .unwrap_or_default()
+        };
+        let system_prompt = memory::with_memories(
+            &summary::with_summary(&role.prompt, session_summary.as_ref()),
+            &memories,
         );
-
-        if !is_first {
-            let _ = self
-                .fill_message_by_session_id(&mut messages, self.session_id.clone())
-                .await;
-        }
-
-        messages.push(
-            ChatCompletionRequestUserMessageArgs::default()
-                .content(message.clone())
-                .build()?
-                .into(),
+        let window = context::build_context(
+            &system_prompt,
+            &history,
+            &message,
+            budget,
+            context_config.strategy,
         );
+        debug!(
+            "context for session {}: {} tokens, {} turns dropped",
+            self.session_id, window.used_tokens, window.dropped_turns
+        );
+        let messages = window.messages;
+        // 放不下的较早对话交给后台压缩成摘要，本轮之后再生效
+        let keep_recent = history.len() - window.dropped_turns;
+        let need_summary = window.dropped_turns > 0
+            || history.len() as i64 >= context_config.history_fetch_limit;
+
+        let params = role.generation_params();
+        let request = LlmRequest {
+            messages: messages.clone(),
+            params: params.clone(),
+        };
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .max_tokens(MAX_TOKENS)
-            .model("deepseek-chat")
-            .messages(messages.clone())
-            .build()?;
-
-        let mut stream = client.chat().create_stream(request).await?;
+        if self.cancel.is_cancelled() {
+            return Ok(());
+        }
+        let mut stream = self.llm.complete_stream(request).await?;
 
         let mut device_message = String::new();
         let mut cut_message = String::new();
+        let mut index = 0;
         let re = Regex::new(r"(,|\\.|，|。|\n\n)").unwrap();
 
-        while let Some(result) = stream.next().await {
+        loop {
+            let result = tokio::select! {
+                biased;
+                _ = self.cancel.cancelled() => break,
+                result = stream.next() => match result {
+                    Some(result) => result,
+                    None => break,
+                },
+            };
             match result {
-                Ok(response) => {
-                    for choice in response.choices.iter() {
-                        if let Some(content) = &choice.delta.content {
-                            // println!("content from stream: {}", content);
-                            device_message.push_str(content);
-
-                            cut_message.push_str(content);
-
-                            let split_text = re.split(&cut_message).collect::<Vec<&str>>();
-                            if split_text.len() > 1 {
-                                for text in split_text.iter().take(split_text.len() - 1) {
-                                    let _ = sender.send(ChatResponse {
-                                        split_text: text.to_string(),
-                                        is_end: false,
-                                    }).await;
-                                }
-
-                                cut_message = split_text[split_text.len() - 1].to_string();
+                Ok(content) => {
+                    // println!("content from stream: {}", content);
+                    if !content.is_empty() {
+                        self.mark(TurnStage::LlmFirstToken);
+                    }
+                    device_message.push_str(&content);
+
+                    cut_message.push_str(&content);
+
+                    let split_text = re.split(&cut_message).collect::<Vec<&str>>();
+                    if split_text.len() > 1 {
+                        for text in split_text.iter().take(split_text.len() - 1) {
+                            self.mark(TurnStage::FirstSentence);
+                            let response = self.chat_response(index, text.to_string(), false);
+                            if sender.send(response).await.is_err() {
+                                // 接收方已经关闭，没人要后续内容了
+                                self.cancel.cancel();
                             }
+                            index += 1;
                         }
+
+                        cut_message = split_text[split_text.len() - 1].to_string();
                     }
                 }
                 Err(err) => {
-                    return Err(anyhow::anyhow!("error: {err}"));
+                    return Err(err);
                 }
             }
         }
 
-        if cut_message.len() > 0 {
-            let _ = sender.send(ChatResponse {
-                split_text: cut_message.clone(),
-                is_end: true,
-            }).await;
-        } else {
-            let _ = sender.send(ChatResponse {
-                split_text: "".to_string(),
-                is_end: true,
-            }).await;
+        // 提前结束时丢弃 stream，上游请求随之断开
+        drop(stream);
+        if self.cancel.is_cancelled() {
+            debug!(
+                "generation cancelled for session {} after {} chunks",
+                self.session_id, index
+            );
+            if !device_message.is_empty() && persist_partial_reply() {
+                if is_first {
+                    let _ = self.finish_insert_session(&memories).await;
+                }
+                let _ = self
+                    .finish_insert_message(message, device_message, true)
+                    .await;
+            }
+            return Ok(());
         }
 
+        self.mark(TurnStage::FirstSentence);
+        let _ = sender
+            .send(self.chat_response(index, cut_message.clone(), true))
+            .await;
+
         if is_first {
-            let _ = self.finish_insert_session().await;
+            let _ = self.finish_insert_session(&memories).await;
         }
 
         let _ = self
-            .finish_insert_message(message.clone(), device_message.clone())
+            .finish_insert_message(message.clone(), device_message.clone(), false)
             .await;
 
+        if memory::extraction_enabled() {
+            memory::spawn_extract(
+                self.db_pool.clone(),
+                self.llm.clone(),
+                self.user_id.clone(),
+                self.role_id.clone(),
+                message.clone(),
+                device_message.clone(),
+                role.model.clone(),
+            );
+        }
+
+        if need_summary {
+            // 加上刚落库的这一轮
+            summary::spawn_summarize(
+                self.db_pool.clone(),
+                self.llm.clone(),
+                self.session_id.clone(),
+                keep_recent + 1,
+                role.model.clone(),
+            );
+        }
+
         let self_message = message.clone();
-        let device_id = self.user_id.clone();
+        // 设备的对话推送到其主人的 app topic
+        let user_id = self.user_id.clone();
         let device_message_clone_for_mqtt = device_message.clone();
 
         tokio::spawn(async move {
             let _ =
-                mqtt::publish_message(self_message, device_message_clone_for_mqtt, device_id).await;
+                mqtt::publish_message(self_message, device_message_clone_for_mqtt, user_id).await;
         });
 
         if is_first {
             let title = self
-                .generate_session_title(messages, device_message)
+                .generate_session_title(messages, device_message, params)
                 .await?;
             self.save_session_title(title).await?;
         }
@@ -366,16 +512,20 @@ impl Chat {
         page: i64,
         page_size: i64,
     ) -> Result<ChatHistoryResponse, Box<dyn std::error::Error>> {
+        let conn = &mut self.db_pool.get()?;
         let sessions = schema::sessions::table
             .filter(schema::sessions::user_id.eq(self.user_id.clone()))
             .order(schema::sessions::updated_at.desc())
             .limit(page_size)
             .offset(page * page_size)
             .select(Session::as_select())
-            .load(&mut self.db_pool.get()?)?;
+            .load(conn)?;
+        let total = schema::sessions::table
+            .filter(schema::sessions::user_id.eq(self.user_id.clone()))
+            .count()
+            .get_result::<i64>(conn)?;
 
         let mut history = Vec::new();
-        let total = sessions.len() as i64;
         for session in sessions {
             history.push(History {
                 chat_id: session.session_id.clone(),
@@ -395,45 +545,56 @@ impl Chat {
         })
     }
 
+    /// 会话不存在或不属于该用户时返回 None
     pub async fn get_chat_session_history(
         &self,
         page: i64,
         page_size: i64,
-    ) -> Result<ChatSessionHistoryResponse, Box<dyn std::error::Error>> {
+    ) -> Result<Option<ChatSessionHistoryResponse>, Box<dyn std::error::Error>> {
+        let conn = &mut self.db_pool.get()?;
+        if find_user_session(conn, &self.user_id, &self.session_id)?.is_none() {
+            return Ok(None);
+        }
+
         let sections = schema::sections::table
             .filter(schema::sections::session_id.eq(self.session_id.clone()))
             .order(schema::sections::updated_at.desc())
             .limit(page_size)
             .offset(page * page_size)
             .select(Section::as_select())
-            .load(&mut self.db_pool.get()?)?;
+            .load(conn)?;
+        let total = schema::sections::table
+            .filter(schema::sections::session_id.eq(self.session_id.clone()))
+            .count()
+            .get_result::<i64>(conn)?;
 
         let mut history = Vec::new();
-        let total = sections.len() as i64;
         for section in sections {
             history.push(ChatSessionHistoryHistory {
                 id: section.section_id.clone(),
                 user: section.user_message.clone(),
                 assistant: section.assistant_message.clone(),
+                is_partial: section.is_partial,
             });
         }
 
-        Ok(ChatSessionHistoryResponse {
+        Ok(Some(ChatSessionHistoryResponse {
             code: 0,
             msg: "".to_string(),
             history,
             page,
             limit: page_size,
             total,
-        })
+        }))
     }
 
     pub async fn add_role(
         &self,
         name: String,
         prompt: String,
+        generation: GenerationParams,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let role = role::Role {
+        let mut role = role::Role {
             id: utils::gen_new_id(),
             is_default: false,
             created_by: self.user_id.clone(),
@@ -444,7 +605,16 @@ impl Chat {
             prompt,
             created_at: SystemTime::now(),
             updated_at: SystemTime::now(),
+            model: None,
+            temperature: None,
+            top_p: None,
+            max_tokens: None,
+            stop_sequences: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            context_token_budget: None,
         };
+        role.set_generation_params(generation);
 
         diesel::insert_into(schema::roles::table)
             .values(&role)
@@ -454,104 +624,129 @@ impl Chat {
     }
 }
 
-//目前不需要这个handler
-// pub async fn chat(
-//     State(mut app_state): State<AppState>,
-//     headers: HeaderMap,
-//     Json(request): Json<ChatRequest>,
-// ) -> impl IntoResponse {
-//     println!(
-//         "{}, {:?}",
-//         serde_json::to_string(&request).unwrap(),
-//         headers
-//     );
-
-//     // let mut chat = Chat::new(
-//     //     request.user_id,
-//     //     request.session_id,
-//     //     request.role_id,
-//     //     &mut app_state,
-//     // );
-//     // if let Ok(response) = chat.on_recv_message(request.message).await {
-//     //     return Json(response).into_response();
-//     // } else {
-//     //     return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-//     // }
-// }
+/// 文字聊天，回复按分句以 SSE 事件推送，最后一句 is_end 为 true
+pub async fn chat(
+    State(app_state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Json(request): Json<ChatRequest>,
+) -> Result<Response, AppError> {
+    debug!("chat {:?}", request);
+    let message = request.message.trim().to_string();
+    if message.is_empty() {
+        return Ok(Json(CommonResponse::error("Empty message")).into_response());
+    }
+
+    let role_id = resolve_role_id(
+        &mut *app_state.db_pool.get()?,
+        &user.user_id,
+        request.role_id,
+    )?;
+
+    let cancel = CancellationToken::new();
+    let chat = Chat::new(
+        user.user_id,
+        request.session_id,
+        role_id,
+        app_state.db_pool.clone(),
+    )
+    .with_cancel_token(cancel.clone());
+    let receiver = chat.on_recv_message(message).await?;
+
+    // 客户端断开时 axum 丢弃这个 stream，guard 随之取消生成
+    let state = Some((receiver, cancel.drop_guard()));
+    // 通道关闭前没有收到 is_end，说明生成中途出错
+    let stream = futures_util::stream::unfold(state, |state| async move {
+        let (mut receiver, guard) = state?;
+        let event = match receiver.recv().await {
+            Some(response) => {
+                let is_end = response.is_end;
+                let event = Event::default().event(SSE_EVENT_CHUNK).json_data(&response);
+                if is_end {
+                    // 正常结束，不需要再取消
+                    guard.disarm();
+                    return Some((event, None));
+                }
+                event
+            }
+            None => {
+                return Some((
+                    Ok(Event::default()
+                        .event(SSE_EVENT_ERROR)
+                        .data("Failed to generate reply")),
+                    None,
+                ))
+            }
+        };
+        Some((event, Some((receiver, guard))))
+    });
+
+    Ok(Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
 
 pub async fn chat_history(
     app_state: State<AppState>,
+    Extension(user): Extension<CurrentUser>,
     Query(query): Query<ChatHistoryRequest>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, AppError> {
     debug!("hello {:?}, {:?}", query, headers);
-    let user_id = headers
-        .get("x-oz-user-id")
-        .ok_or(AppError(anyhow::anyhow!("User id not found")))?
-        .to_str()
-        .unwrap_or("");
     let chat = Chat::new(
-        user_id.to_string(),
+        user.user_id,
         "".to_string(),
         "".to_string(),
         app_state.db_pool.clone(),
     );
-    if let Ok(response) = chat.get_chat_history(query.offset, query.limit).await {
-        return Ok(Json(response).into_response());
-    } else {
-        return Err(AppError(anyhow::anyhow!("Failed to get chat history")));
+    match chat.get_chat_history(query.offset, query.limit).await {
+        Ok(response) => Ok(Json(response).into_response()),
+        Err(_) => Err(AppError(anyhow::anyhow!("Failed to get chat history"))),
     }
 }
 
 pub async fn chat_session_history(
     app_state: State<AppState>,
+    Extension(user): Extension<CurrentUser>,
     headers: HeaderMap,
     Json(request): Json<ChatSessionHistoryRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     debug!("hello {:?}, {:?}", request, headers);
-    let user_id = headers
-        .get("x-oz-user-id")
-        .ok_or(AppError(anyhow::anyhow!("User id not found")))?
-        .to_str()
-        .unwrap_or("");
-
     let chat = Chat::new(
-        user_id.to_string(),
+        user.user_id,
         request.chat_id,
         "".to_string(),
         app_state.db_pool.clone(),
     );
-    if let Ok(response) = chat
+    match chat
         .get_chat_session_history(request.offset, request.limit)
         .await
     {
-        return Ok(Json(response).into_response());
-    } else {
-        return Err(AppError(anyhow::anyhow!(
+        Ok(Some(response)) => Ok(Json(response).into_response()),
+        Ok(None) => Ok(StatusCode::NOT_FOUND.into_response()),
+        Err(_) => Err(AppError(anyhow::anyhow!(
             "Failed to get chat session history"
-        )));
+        ))),
     }
 }
 
 pub async fn add_role(
     State(app_state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
     header: HeaderMap,
     Json(request): Json<AddRoleRequest>,
 ) -> Result<impl IntoResponse, AppError> {
     debug!("add_role {:?}, {:?}", request, header);
-    let user_id = header
-        .get("x-oz-user-id")
-        .ok_or(AppError(anyhow::anyhow!("User id not found")))?
-        .to_str()
-        .unwrap_or("");
-
     let chat = Chat::new(
-        user_id.to_string(),
+        user.user_id,
         "".to_string(),
         "".to_string(),
         app_state.db_pool.clone(),
     );
-    let _ = chat.add_role(request.name, request.prompt).await;
+    let generation = request.generation.unwrap_or_default();
+    if let Err(msg) = generation.validate() {
+        return Ok(Json(CommonResponse::error(&msg)).into_response());
+    }
+    let _ = chat.add_role(request.name, request.prompt, generation).await;
 
     Ok(StatusCode::OK.into_response())
 }
@@ -564,11 +759,14 @@ mod tests {
     };
 
     use super::*;
+    use crate::config::OZ_SERVER_CONFIG;
+    use crate::services::llm::mock::MockProvider;
     // use log::{Builder, LevelFilter, Record};
     // use std::io::Write;
     // use std::time::Local;
 
     #[tokio::test]
+    #[ignore = "needs a local database and the openai api"]
     async fn test_chat() {
         // Builder::from_default_env()
         //     .format(|buf, record| {
@@ -595,7 +793,7 @@ mod tests {
 
         let app_state = AppState::new(pool, OZ_SERVER_CONFIG.clone());
 
-        let mut chat = Chat::new(
+        let chat = Chat::new(
             "default_user".to_string(),
             "".to_string(),
             "default_role".to_string(),
@@ -612,4 +810,41 @@ mod tests {
             println!("chat_response: {:?}", chat_response.split_text);
         }
     }
+
+    #[tokio::test]
+    #[ignore = "needs the database from database_url"]
+    async fn test_chat_with_mock_provider() {
+        let database_url = OZ_SERVER_CONFIG.get::<String>("database_url").unwrap();
+        let pool = Pool::builder()
+            .build(ConnectionManager::<PgConnection>::new(database_url))
+            .expect("Failed to create pool.");
+
+        let chat = Chat::new(
+            "default_user".to_string(),
+            "".to_string(),
+            DEFAULT_ROLE_ID.to_string(),
+            pool,
+        )
+        .with_llm_provider(Arc::new(MockProvider::new(Some(
+            "你好，我在。".to_string(),
+        ))));
+        let mut receiver = chat.on_recv_message("在吗".to_string()).await.unwrap();
+        let mut responses = Vec::new();
+        while let Some(response) = receiver.recv().await {
+            responses.push(response);
+        }
+
+        // 按中文标点分句，最后一条是结束标记
+        let texts = responses
+            .iter()
+            .map(|r| r.split_text.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(texts, vec!["你好", "我在", ""]);
+        assert!(responses.iter().enumerate().all(|(i, r)| r.index == i));
+        assert!(responses.last().unwrap().is_end);
+        // 新建的会话 id 在每一句里都一样
+        let session_id = &responses[0].session_id;
+        assert!(!session_id.is_empty());
+        assert!(responses.iter().all(|r| &r.session_id == session_id));
+    }
 }
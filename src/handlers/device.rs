@@ -0,0 +1,384 @@
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::Response,
+    Extension,
+};
+use diesel::prelude::*;
+use diesel::PgConnection;
+
+use crate::constant::{
+    PAIRING_CODE_TTL_SECS, PAIRING_CONFIRM_LOCKOUT_SECS, PAIRING_CONFIRM_MAX_FAILURES,
+    PAIRING_STATUS_PAIRED, PAIRING_STATUS_PENDING,
+};
+use crate::handlers::account::{hash_password, internal_error, issue_token_pair, verify_password};
+use crate::handlers::auth::unauthorized;
+use crate::models::{
+    device::Device,
+    device_pairing::DevicePairing,
+    schema::{device_pairings, devices, refresh_tokens},
+};
+use crate::structures::device::{
+    ConfirmPairingRequest, DeviceInfo, DeviceListResponse, DeviceTokenRequest, PairingPayload,
+    PairingPollPayload, PairingPollRequest, PairingPollResponse, PairingRequest, PairingResponse,
+};
+use crate::structures::user::{CurrentUser, TokenResponse};
+use crate::structures::{AppState, CommonResponse};
+use crate::utils;
+use crate::utils::rate_limit::FailureLimiter;
+
+const MAX_DEVICE_ID_LEN: usize = 64;
+const PAIRING_CODE_RETRY: usize = 5;
+
+lazy_static::lazy_static! {
+    /// 按用户限制输错配对码的次数，防止猜码
+    static ref CONFIRM_FAILURES: FailureLimiter = FailureLimiter::new(
+        PAIRING_CONFIRM_MAX_FAILURES,
+        Duration::from_secs(PAIRING_CONFIRM_LOCKOUT_SECS),
+    );
+}
+
+pub fn revoke_device_refresh_tokens(conn: &mut PgConnection, device_id: &str) -> QueryResult<usize> {
+    diesel::update(
+        refresh_tokens::table
+            .filter(refresh_tokens::device_id.eq(device_id))
+            .filter(refresh_tokens::revoked.eq(false)),
+    )
+    .set((
+        refresh_tokens::revoked.eq(true),
+        refresh_tokens::updated_at.eq(SystemTime::now()),
+    ))
+    .execute(conn)
+}
+
+/// 设备发起配对，拿到配对码展示给用户，并用 pairing_secret 轮询结果
+pub async fn request_pairing(
+    State(state): State<AppState>,
+    Json(payload): Json<PairingRequest>,
+) -> Result<Json<PairingResponse>, Response> {
+    let device_id = payload.device_id.trim().to_string();
+    if device_id.is_empty() || device_id.len() > MAX_DEVICE_ID_LEN {
+        return Ok(Json(PairingResponse::error("Invalid device id")));
+    }
+
+    let existing = {
+        let conn = &mut state.db_pool.get().map_err(internal_error)?;
+        devices::table
+            .find(&device_id)
+            .select(Device::as_select())
+            .first(conn)
+            .optional()
+            .map_err(internal_error)?
+    };
+    // 已经有主人的设备只能凭当前的设备凭证重新配对，否则谁都能把它抢走
+    if let Some(existing) = existing.filter(|d| d.user_id.is_some()) {
+        let valid = match (existing.secret_hash, payload.device_secret) {
+            (Some(secret_hash), Some(device_secret)) => verify_password(device_secret, secret_hash)
+                .await
+                .map_err(internal_error)?,
+            _ => false,
+        };
+        if !valid {
+            return Err(unauthorized(
+                "device_already_paired",
+                "Device is already paired",
+            ));
+        }
+    }
+
+    let conn = &mut state.db_pool.get().map_err(internal_error)?;
+    let now = SystemTime::now();
+
+    let device = Device {
+        id: device_id.clone(),
+        user_id: None,
+        name: payload.name.unwrap_or_default(),
+        secret_hash: None,
+        paired_at: None,
+        created_at: now,
+        updated_at: now,
+    };
+    diesel::insert_into(devices::table)
+        .values(&device)
+        .on_conflict_do_nothing()
+        .execute(conn)
+        .map_err(internal_error)?;
+
+    // 同一台设备同时只保留一个配对请求
+    diesel::delete(device_pairings::table.filter(device_pairings::device_id.eq(&device_id)))
+        .execute(conn)
+        .map_err(internal_error)?;
+
+    let mut code = None;
+    for _ in 0..PAIRING_CODE_RETRY {
+        let candidate = utils::gen_pairing_code();
+        let in_use = device_pairings::table
+            .filter(device_pairings::code.eq(&candidate))
+            .filter(device_pairings::expires_at.gt(now))
+            .count()
+            .get_result::<i64>(conn)
+            .map_err(internal_error)?;
+        if in_use == 0 {
+            code = Some(candidate);
+            break;
+        }
+    }
+    let code = match code {
+        Some(code) => code,
+        None => return Ok(Json(PairingResponse::error("Please retry later"))),
+    };
+
+    let pairing = DevicePairing {
+        id: utils::gen_new_id(),
+        code,
+        device_id,
+        pairing_secret: utils::gen_secret(),
+        confirmed_by: None,
+        expires_at: now + Duration::from_secs(PAIRING_CODE_TTL_SECS),
+        created_at: now,
+    };
+    diesel::insert_into(device_pairings::table)
+        .values(&pairing)
+        .execute(conn)
+        .map_err(internal_error)?;
+
+    Ok(Json(PairingResponse::success(PairingPayload {
+        code: pairing.code,
+        pairing_secret: pairing.pairing_secret,
+        expires_in: PAIRING_CODE_TTL_SECS,
+    })))
+}
+
+/// 已登录的 app 用户输入设备上显示的配对码，确认绑定
+pub async fn confirm_pairing(
+    State(state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Json(payload): Json<ConfirmPairingRequest>,
+) -> Result<Json<CommonResponse>, Response> {
+    if CONFIRM_FAILURES.is_locked(&user.user_id) {
+        return Ok(Json(CommonResponse::error(
+            "Too many failed attempts, please retry later",
+        )));
+    }
+
+    let conn = &mut state.db_pool.get().map_err(internal_error)?;
+    let now = SystemTime::now();
+
+    let pairing = device_pairings::table
+        .filter(device_pairings::device_id.eq(payload.device_id.trim()))
+        .filter(device_pairings::code.eq(payload.code.trim()))
+        .filter(device_pairings::expires_at.gt(now))
+        .filter(device_pairings::confirmed_by.is_null())
+        .select(DevicePairing::as_select())
+        .first(conn)
+        .optional()
+        .map_err(internal_error)?;
+    let pairing = match pairing {
+        Some(pairing) => pairing,
+        None => {
+            CONFIRM_FAILURES.record_failure(&user.user_id);
+            return Ok(Json(CommonResponse::error(
+                "Invalid or expired pairing code",
+            )));
+        }
+    };
+    CONFIRM_FAILURES.reset(&user.user_id);
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::update(device_pairings::table.find(&pairing.id))
+            .set(device_pairings::confirmed_by.eq(&user.user_id))
+            .execute(conn)?;
+
+        diesel::update(devices::table.find(&pairing.device_id))
+            .set((
+                devices::user_id.eq(&user.user_id),
+                devices::secret_hash.eq(None::<String>),
+                devices::paired_at.eq(now),
+                devices::updated_at.eq(now),
+            ))
+            .execute(conn)?;
+
+        if let Some(name) = payload.name.as_ref().filter(|name| !name.is_empty()) {
+            diesel::update(devices::table.find(&pairing.device_id))
+                .set(devices::name.eq(name))
+                .execute(conn)?;
+        }
+
+        // 设备换了主人，之前签发给它的 refresh token 全部作废
+        revoke_device_refresh_tokens(conn, &pairing.device_id)?;
+        Ok(())
+    })
+    .map_err(internal_error)?;
+
+    Ok(Json(CommonResponse::success()))
+}
+
+/// 设备轮询配对结果，配对完成时一次性下发设备凭证和 token
+pub async fn poll_pairing(
+    State(state): State<AppState>,
+    Json(payload): Json<PairingPollRequest>,
+) -> Result<Json<PairingPollResponse>, Response> {
+    let pairing = {
+        let conn = &mut state.db_pool.get().map_err(internal_error)?;
+        device_pairings::table
+            .filter(device_pairings::device_id.eq(&payload.device_id))
+            .filter(device_pairings::pairing_secret.eq(&payload.pairing_secret))
+            .select(DevicePairing::as_select())
+            .first(conn)
+            .optional()
+            .map_err(internal_error)?
+    };
+    let pairing = match pairing {
+        Some(pairing) => pairing,
+        None => return Err(unauthorized("pairing_invalid", "Unknown pairing request")),
+    };
+
+    let owner = match pairing.confirmed_by {
+        Some(owner) => owner,
+        None if pairing.expires_at <= SystemTime::now() => {
+            return Ok(Json(PairingPollResponse::error("Pairing code expired")))
+        }
+        None => {
+            return Ok(Json(PairingPollResponse::success(PairingPollPayload {
+                status: PAIRING_STATUS_PENDING.to_string(),
+                device_secret: None,
+                tokens: None,
+            })))
+        }
+    };
+
+    let device_secret = utils::gen_secret();
+    let secret_hash = hash_password(device_secret.clone())
+        .await
+        .map_err(internal_error)?;
+
+    let conn = &mut state.db_pool.get().map_err(internal_error)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::update(devices::table.find(&pairing.device_id))
+            .set((
+                devices::secret_hash.eq(Some(secret_hash)),
+                devices::updated_at.eq(SystemTime::now()),
+            ))
+            .execute(conn)?;
+        diesel::delete(device_pairings::table.find(&pairing.id)).execute(conn)?;
+        Ok(())
+    })
+    .map_err(internal_error)?;
+
+    let tokens =
+        issue_token_pair(conn, &owner, Some(&pairing.device_id)).map_err(internal_error)?;
+
+    Ok(Json(PairingPollResponse::success(PairingPollPayload {
+        status: PAIRING_STATUS_PAIRED.to_string(),
+        device_secret: Some(device_secret),
+        tokens: Some(tokens),
+    })))
+}
+
+/// 已配对设备用自己的凭证换取 token，token 中的用户是设备的主人
+pub async fn device_token(
+    State(state): State<AppState>,
+    Json(payload): Json<DeviceTokenRequest>,
+) -> Result<Json<TokenResponse>, Response> {
+    let device = {
+        let conn = &mut state.db_pool.get().map_err(internal_error)?;
+        devices::table
+            .find(&payload.device_id)
+            .select(Device::as_select())
+            .first(conn)
+            .optional()
+            .map_err(internal_error)?
+    };
+
+    let (owner, secret_hash) = match device {
+        Some(Device {
+            user_id: Some(owner),
+            secret_hash: Some(secret_hash),
+            ..
+        }) => (owner, secret_hash),
+        _ => return Err(unauthorized("device_not_paired", "Device is not paired")),
+    };
+
+    let valid = verify_password(payload.device_secret, secret_hash)
+        .await
+        .map_err(internal_error)?;
+    if !valid {
+        return Err(unauthorized(
+            "invalid_credentials",
+            "Invalid device credential",
+        ));
+    }
+
+    let conn = &mut state.db_pool.get().map_err(internal_error)?;
+    let tokens =
+        issue_token_pair(conn, &owner, Some(&payload.device_id)).map_err(internal_error)?;
+    Ok(Json(TokenResponse::success(tokens)))
+}
+
+pub async fn list_devices(
+    State(state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+) -> Result<Json<DeviceListResponse>, StatusCode> {
+    let conn = &mut state
+        .db_pool
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let results = devices::table
+        .filter(devices::user_id.eq(&user.user_id))
+        .order(devices::paired_at.desc())
+        .select(Device::as_select())
+        .load(conn)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let devices = results
+        .into_iter()
+        .map(|d| DeviceInfo {
+            id: d.id,
+            name: d.name,
+            paired_at: d.paired_at.map(utils::to_unix_secs).unwrap_or(0),
+        })
+        .collect();
+
+    Ok(Json(DeviceListResponse {
+        code: 0,
+        msg: "ok".to_string(),
+        devices,
+    }))
+}
+
+pub async fn unpair_device(
+    State(state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Path(device_id): Path<String>,
+) -> Result<Json<CommonResponse>, StatusCode> {
+    let conn = &mut state
+        .db_pool
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let updated = diesel::update(
+        devices::table
+            .filter(devices::id.eq(&device_id))
+            .filter(devices::user_id.eq(&user.user_id)),
+    )
+    .set((
+        devices::user_id.eq(None::<String>),
+        devices::secret_hash.eq(None::<String>),
+        devices::paired_at.eq(None::<SystemTime>),
+        devices::updated_at.eq(SystemTime::now()),
+    ))
+    .execute(conn)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if updated == 0 {
+        return Ok(Json(CommonResponse::error("Device not found")));
+    }
+
+    revoke_device_refresh_tokens(conn, &device_id)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(CommonResponse::success()))
+}
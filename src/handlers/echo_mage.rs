@@ -7,41 +7,50 @@ use axum::{
     Extension,
 };
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
-use llm_audio_toolkit::asr::{volc::VolcanoConfig, volc::VolcanoEchoMage, EchoMage};
-use llm_audio_toolkit::tts::volc::{VolcConfig as TTSConfig, VolcWsTTS};
-use llm_audio_toolkit::tts::SpellCaster;
+use futures_util::{SinkExt, StreamExt};
 use log::debug;
-use serde::Deserialize;
-use serde_json::{json, Value};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
 
-use crate::config::global_cfg::GlobalConfig;
+use crate::config::global_cfg::{AsrConfig, TtsConfig};
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::{
+    DEFAULT_ROLE_ID, DEFAULT_TTS_LOOKAHEAD, DEFAULT_WS_IDLE_TIMEOUT_SECS, TTS_LOOKAHEAD,
+    WS_IDLE_TIMEOUT_SECS,
+};
+use crate::json::ws::{
+    negotiate_transport, negotiate_version, AudioFrameHeader, AudioTransport, ClientMessage,
+    ErrorCode, ServerEvent, ServerMessage, StartSessionPayload, TurnDetection,
+    AUDIO_FRAME_FLAG_END, AUDIO_FRAME_KIND_INPUT, AUDIO_FRAME_KIND_OUTPUT, MIN_PROTOCOL_VERSION,
+    PROTOCOL_VERSION,
+};
+use crate::models::role::Role;
+use crate::services::audio::codec::{self, OpusConfig, OpusPacketDecoder};
+use crate::services::audio::{Container, OutputEncoder, OutputFormat, PcmFormat};
+use crate::services::metrics::{self, TurnStage, TurnTimings};
+use crate::services::recording::{TurnRecording, RECORDING_STORAGE};
+use crate::services::vad::{Vad, VadConfig, VadEvent};
+use crate::services::voice::{instrumented, AsrSession, SpeechChunk, ASR_BACKEND, TTS_BACKEND};
 use crate::{
-    handlers::chat::Chat,
+    handlers::chat::{find_user_session, Chat, ChatResponse},
+    handlers::role::{find_role, resolve_role_id},
     structures::{user::CurrentUser, AppState},
+    utils,
 };
 
-const START_SESSION_MSG: &str = "start_session";
-const AUDIO_INPUT_CHUNK_MSG: &str = "audio_input_chunk";
-const AUDIO_INPUT_FINISH_MSG: &str = "audio_input_finish";
-
-// 定义会话开始的请求结构
-#[derive(Debug, Deserialize)]
-struct StartSessionPayload {
-    session_id: String,
-    input_format: String,
-    output_format: String,
-    sample_rate: u32,
-    output_sample_rate: u32,
-    round: u32,
-}
-
-#[derive(Debug, Deserialize)]
-struct WebSocketMessage {
-    #[serde(rename = "type")]
-    msg_type: String,
-    payload: Option<Value>,
-}
+// 服务端 VAD 能处理的输入格式
+const PCM_INPUT_FORMATS: [&str; 2] = ["pcm", "raw"];
+// 每条消息一个 Opus 包，服务端解码成 PCM
+const OPUS_INPUT_FORMAT: &str = "opus";
+// 开口前保留的音频时长
+const PREROLL_MS: usize = 300;
+// 每句合成结果最多缓存的音频块数
+const SPEECH_CHUNK_BUFFER: usize = 64;
 
 // WebSocket upgrade handler
 pub async fn ws_handler(
@@ -52,222 +61,1131 @@ pub async fn ws_handler(
     ws.on_upgrade(move |socket| handle_socket(socket, app_state, user))
 }
 
-// 处理 WebSocket 连接
-async fn handle_socket(mut socket: WebSocket, mut app_state: AppState, user: CurrentUser) {
+/// 客户端输入：JSON 文本帧，或者只带音频的二进制帧
+enum Incoming {
+    Message(ClientMessage),
+    Audio(Vec<u8>),
+}
+
+/// 会话协商出的音频输出方式
+#[derive(Clone)]
+struct OutputSettings {
+    /// 协商出的协议版本，决定能发哪些事件
+    protocol_version: u32,
+    transport: AudioTransport,
+    tts_config: TtsConfig,
+    /// 服务端要把 TTS 的 PCM 转成的格式，为空时 TTS 直接输出客户端要的格式
+    format: Option<OutputFormat>,
+    /// 最多提前合成几句
+    tts_lookahead: usize,
+    /// 本轮回复的录音，只在开启录音时有
+    recording: Option<TurnRecording>,
+    /// 本轮各阶段的时间点
+    timings: Option<Arc<TurnTimings>>,
+}
+
+/// 正在进行的一轮回复：LLM 生成和 TTS 推送都在后台任务中
+struct Responder {
+    round: u32,
+    cancel: CancellationToken,
+    handle: JoinHandle<()>,
+    /// 音频已经完整推送给客户端的最后一句的序号，-1 表示一句都没有
+    last_delivered: Arc<AtomicI64>,
+    timings: Arc<TurnTimings>,
+}
+
+fn idle_timeout() -> Duration {
+    Duration::from_secs(
+        OZ_SERVER_CONFIG
+            .get::<u64>(WS_IDLE_TIMEOUT_SECS)
+            .unwrap_or(DEFAULT_WS_IDLE_TIMEOUT_SECS),
+    )
+}
+
+async fn send_event(out: &mpsc::Sender<Message>, round: u32, message: ServerMessage) -> bool {
+    let event = ServerEvent { message, round };
+    match serde_json::to_string(&event) {
+        Ok(text) => out.send(Message::Text(text.into())).await.is_ok(),
+        Err(e) => {
+            error!("Failed to serialize server event: {}", e);
+            false
+        }
+    }
+}
+
+/// 协商的版本不支持这条消息时不发，当作发送成功
+async fn send_versioned_event(
+    out: &mpsc::Sender<Message>,
+    round: u32,
+    protocol_version: u32,
+    message: ServerMessage,
+) -> bool {
+    if protocol_version < message.min_protocol_version() {
+        return true;
+    }
+    send_event(out, round, message).await
+}
+
+fn is_opus_input(input_format: &str) -> bool {
+    input_format.eq_ignore_ascii_case(OPUS_INPUT_FORMAT)
+}
+
+/// 输入送给 VAD 和识别时是不是 PCM，opus 会先解码
+fn decodes_to_pcm(input_format: &str) -> bool {
+    PCM_INPUT_FORMATS.contains(&input_format.to_lowercase().as_str()) || is_opus_input(input_format)
+}
+
+/// 会话要求服务端 VAD 且输入是 PCM 时才启用
+fn session_vad(payload: &StartSessionPayload) -> Option<Vad> {
+    if payload.turn_detection != TurnDetection::ServerVad {
+        return None;
+    }
+    if !decodes_to_pcm(&payload.input_format) {
+        info!(
+            "server_vad needs pcm input, got {}, falling back to manual",
+            payload.input_format
+        );
+        return None;
+    }
+
+    let mut config = VadConfig::load();
+    if let Some(silence_ms) = payload.vad_silence_ms {
+        config.silence_ms = silence_ms;
+    }
+    if let Some(min_speech_ms) = payload.vad_min_speech_ms {
+        config.min_speech_ms = min_speech_ms;
+    }
+    Some(Vad::new(config, payload.sample_rate))
+}
+
+/// 按协商的传输方式推送一段音频
+async fn send_audio(
+    out: &mpsc::Sender<Message>,
+    round: u32,
+    transport: AudioTransport,
+    seq: u32,
+    flags: u8,
+    audio: &[u8],
+) -> bool {
+    match transport {
+        AudioTransport::Base64 => {
+            let chunk = ServerMessage::AudioOutputChunk(BASE64.encode(audio));
+            send_event(out, round, chunk).await
+        }
+        AudioTransport::Binary => {
+            let header = AudioFrameHeader {
+                kind: AUDIO_FRAME_KIND_OUTPUT,
+                flags,
+                stream_id: round as u16,
+                seq,
+            };
+            out.send(Message::Binary(header.encode(audio).into()))
+                .await
+                .is_ok()
+        }
+    }
+}
+
+async fn send_error(out: &mpsc::Sender<Message>, round: u32, code: ErrorCode, msg: &str) -> bool {
+    let error = ServerMessage::Error {
+        code,
+        msg: msg.to_string(),
+    };
+    send_event(out, round, error).await
+}
+
+/// 会话使用的角色：start_session 指定的优先，否则是用户选中的角色
+fn session_role(
+    app_state: &AppState,
+    user_id: &str,
+    requested: Option<String>,
+) -> anyhow::Result<Option<Role>> {
+    let mut conn = app_state.db_pool.get()?;
+    let role_id = resolve_role_id(&mut conn, user_id, requested)?;
+    Ok(find_role(&mut conn, &role_id)?)
+}
+
+/// 只接续属于该用户的会话，别人的或不存在的会话从新会话开始
+fn owned_session_id(
+    app_state: &AppState,
+    user_id: &str,
+    requested: String,
+) -> anyhow::Result<String> {
+    if requested.is_empty() {
+        return Ok(requested);
+    }
+    let mut conn = app_state.db_pool.get()?;
+    match find_user_session(&mut conn, user_id, &requested)? {
+        Some(_) => Ok(requested),
+        None => {
+            info!(
+                "Session {} not found for user {}, starting a new one",
+                requested, user_id
+            );
+            Ok(String::new())
+        }
+    }
+}
+
+/// 会话的识别配置：服务端配置打底，start_session 里的格式和采样率优先
+fn session_asr_config(base: &AsrConfig, payload: &StartSessionPayload) -> AsrConfig {
+    let mut config = base.clone();
+    if decodes_to_pcm(&payload.input_format) {
+        // 火山识别把 PCM 叫做 raw
+        config.audio_format = "raw".to_string();
+    } else if !payload.input_format.is_empty() {
+        config.audio_format = payload.input_format.to_lowercase();
+    }
+    if payload.sample_rate > 0 {
+        config.sample_rate = payload.sample_rate;
+    }
+    config
+}
+
+/// 会话的合成配置，start_session 里的输出格式优先，用角色的音色朗读
+fn session_tts_config(base: &TtsConfig, payload: &StartSessionPayload, role: &Role) -> TtsConfig {
+    let mut config = base.clone();
+    if !role.voice_id.is_empty() {
+        config.voice_type = role.voice_id.clone();
+    }
+    if Container::parse(&payload.output_format).is_some() {
+        // 服务端自己转换，TTS 只输出 PCM
+        config.enc_format = "pcm".to_string();
+    } else if !payload.output_format.is_empty() {
+        config.enc_format = payload.output_format.to_lowercase();
+    }
+    config
+}
+
+/// 客户端要的是服务端能转换的格式时，算出转换目标，未指定的参数沿用 TTS 的输出
+fn session_output_format(payload: &StartSessionPayload) -> Result<Option<OutputFormat>, String> {
+    let container = match Container::parse(&payload.output_format) {
+        Some(container) => container,
+        None => return Ok(None),
+    };
+    let sample_rate = match payload.output_sample_rate {
+        0 => TTS_BACKEND.sample_rate(),
+        sample_rate => sample_rate,
+    };
+    let mut pcm = PcmFormat {
+        sample_rate,
+        channels: payload.output_channels.unwrap_or(1),
+        bits_per_sample: payload.output_bits_per_sample.unwrap_or(16),
+    };
+    let mut opus = OpusConfig::load();
+    if container.is_opus() {
+        // Opus 编码器只接受 16 位输入
+        pcm.bits_per_sample = 16;
+        codec::check_format(pcm.sample_rate, pcm.channels)?;
+        if let Some(frame_ms) = payload.opus_frame_ms {
+            opus.frame_ms = frame_ms;
+        }
+        if let Some(packet_loss_percent) = payload.opus_packet_loss_percent {
+            opus.packet_loss_percent = packet_loss_percent;
+        }
+        opus.validate()?;
+    }
+    pcm.validate()?;
+    Ok(Some(OutputFormat {
+        container,
+        pcm,
+        opus,
+    }))
+}
+
+/// 编码一块合成的音频，最后一块时把编码器里剩下的也输出
+fn encode_speech(
+    encoder: &mut OutputEncoder,
+    audio: &[u8],
+    is_last: bool,
+) -> anyhow::Result<Vec<SpeechChunk>> {
+    let mut payloads = encoder.encode(audio)?;
+    if is_last {
+        payloads.extend(encoder.finish()?);
+    }
+
+    let count = payloads.len();
+    let mut chunks = payloads
+        .into_iter()
+        .enumerate()
+        .map(|(i, audio)| SpeechChunk {
+            audio,
+            is_last: is_last && i + 1 == count,
+        })
+        .collect::<Vec<SpeechChunk>>();
+    // 编码后可能没有数据，结束标记还是要发
+    if is_last && chunks.is_empty() {
+        chunks.push(SpeechChunk {
+            audio: Vec::new(),
+            is_last: true,
+        });
+    }
+    Ok(chunks)
+}
+
+async fn start_asr(config: &AsrConfig) -> anyhow::Result<Box<dyn AsrSession>> {
+    ASR_BACKEND.start(config).await
+}
+
+/// 一句回复和它正在合成的音频
+struct PendingSentence {
+    response: ChatResponse,
+    audio: mpsc::Receiver<SpeechChunk>,
+}
+
+fn tts_lookahead() -> usize {
+    OZ_SERVER_CONFIG
+        .get::<usize>(TTS_LOOKAHEAD)
+        .unwrap_or(DEFAULT_TTS_LOOKAHEAD)
+        .max(1)
+}
+
+/// 在后台合成一句话，音频按顺序放进返回的 channel，合成失败时 channel 直接关闭。
+/// 合成结束后才释放 permit
+fn spawn_synthesis(
+    index: usize,
+    text: String,
+    output: OutputSettings,
+    cancel: CancellationToken,
+    permit: OwnedSemaphorePermit,
+) -> mpsc::Receiver<SpeechChunk> {
+    let (sender, receiver) = mpsc::channel(SPEECH_CHUNK_BUFFER);
+    // 结束标记可能不带文字，没有要合成的内容
+    if text.trim().is_empty() {
+        return receiver;
+    }
+
+    tokio::spawn(async move {
+        let start = std::time::Instant::now();
+        let mut speech = match TTS_BACKEND.synthesize(&text, &output.tts_config).await {
+            Ok(speech) => speech,
+            Err(e) => {
+                error!("Failed to synthesize speech: {}", e);
+                return;
+            }
+        };
+        let native = PcmFormat::mono16(TTS_BACKEND.sample_rate());
+        let encoder = output
+            .format
+            .map(|format| OutputEncoder::new(native, format))
+            .transpose();
+        let mut encoder = match encoder {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                error!("Failed to create audio encoder: {}", e);
+                return;
+            }
+        };
+
+        // 只有 TTS 输出 PCM 时才能录下来
+        let recording = output
+            .recording
+            .filter(|_| output.tts_config.enc_format == "pcm");
+
+        let mut finished = false;
+        'receive: while let Some(chunk) = tokio::select! {
+            _ = cancel.cancelled() => None,
+            chunk = speech.recv() => chunk,
+        } {
+            let is_last = chunk.is_last;
+            finished |= is_last;
+            if let Some(recording) = &recording {
+                recording.append_output(index, &chunk.audio);
+            }
+            let chunks = match encoder.as_mut() {
+                Some(encoder) => match encode_speech(encoder, &chunk.audio, is_last) {
+                    Ok(chunks) => chunks,
+                    Err(e) => {
+                        error!("Failed to encode speech: {}", e);
+                        break;
+                    }
+                },
+                None => vec![chunk],
+            };
+            for chunk in chunks {
+                // 接收方已经不要了 (被打断或者连接断开)
+                if sender.send(chunk).await.is_err() {
+                    break 'receive;
+                }
+            }
+            if is_last {
+                break;
+            }
+        }
+        // 被打断的句子不计入
+        if !cancel.is_cancelled() {
+            instrumented::observe_tts_sentence(TTS_BACKEND.name(), start, finished);
+        }
+        drop(permit);
+    });
+    receiver
+}
+
+/// 把 Chat 的分句合成语音推给客户端，cancel 被取消时立即停止。
+/// 后面的句子在前一句播放时就开始合成，最多提前 tts_lookahead 句，推送顺序不变
+async fn respond(
+    out: mpsc::Sender<Message>,
+    mut chat_receiver: mpsc::Receiver<ChatResponse>,
+    round: u32,
+    output: OutputSettings,
+    cancel: CancellationToken,
+    last_delivered: Arc<AtomicI64>,
+    session_id: Arc<Mutex<String>>,
+) {
+    let transport = output.transport;
+    let protocol_version = output.protocol_version;
+    let recording = output.recording.clone();
+    let timings = output.timings.clone();
+    debug!("chat_receiver ready to recv for tts");
+    // 从Chat的receiver中接收消息并提前开始合成，队列满了就等前面的句子播完。
+    // 同时进行的合成最多 tts_lookahead 句，队列只负责保持顺序
+    let lookahead = output.tts_lookahead;
+    let (queue_sender, mut queue) = mpsc::channel::<PendingSentence>(lookahead);
+    let synthesis_slots = Arc::new(Semaphore::new(lookahead));
+    let producer_cancel = cancel.clone();
+    let producer = tokio::spawn(async move {
+        while let Some(response) = tokio::select! {
+            _ = producer_cancel.cancelled() => None,
+            response = chat_receiver.recv() => response,
+        } {
+            debug!("chat_response: {:?}", response.split_text);
+            let permit = tokio::select! {
+                _ = producer_cancel.cancelled() => break,
+                permit = synthesis_slots.clone().acquire_owned() => match permit {
+                    Ok(permit) => permit,
+                    Err(_) => break,
+                },
+            };
+            let audio = spawn_synthesis(
+                response.index,
+                response.split_text.clone(),
+                output.clone(),
+                producer_cancel.clone(),
+                permit,
+            );
+            if queue_sender
+                .send(PendingSentence { response, audio })
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut seq: u32 = 0;
+    let mut finished = false;
+    while let Some(PendingSentence {
+        response: chat_response,
+        mut audio,
+    }) = tokio::select! {
+        _ = cancel.cancelled() => None,
+        sentence = queue.recv() => sentence,
+    } {
+        // 第一轮可能新建了会话，后续轮次接着这个会话，历史才能累积
+        *session_id.lock().unwrap() = chat_response.session_id.clone();
+
+        // 先发文字再发这一句的音频，客户端按顺序就能把字幕和声音对上
+        let assistant_text = ServerMessage::AssistantText {
+            index: chat_response.index,
+            text: chat_response.split_text.clone(),
+            is_end: chat_response.is_end,
+        };
+        if !send_versioned_event(&out, round, protocol_version, assistant_text).await {
+            cancel.cancel();
+            break;
+        }
+
+        // 处理TTS的音频流
+        let mut end_flag_sent = false;
+        while let Some(chunk) = tokio::select! {
+            _ = cancel.cancelled() => None,
+            chunk = audio.recv() => chunk,
+        } {
+            if !chunk.audio.is_empty() {
+                if let Some(timings) = &timings {
+                    timings.mark(TurnStage::TtsFirstByte);
+                }
+                let flags = if chunk.is_last && chat_response.is_end {
+                    end_flag_sent = true;
+                    AUDIO_FRAME_FLAG_END
+                } else {
+                    0
+                };
+
+                // 发送音频数据到websocket客户端
+                if !send_audio(&out, round, transport, seq, flags, &chunk.audio).await {
+                    error!("Failed to send audio chunk");
+                    cancel.cancel();
+                    break;
+                }
+                seq = seq.wrapping_add(1);
+            }
+
+            if chunk.is_last {
+                break;
+            }
+        }
+
+        if cancel.is_cancelled() {
+            break;
+        }
+        // 最后一句通常没有文字 (或者最后一块音频是空的)，单独发一个空的结束帧
+        if chat_response.is_end
+            && transport == AudioTransport::Binary
+            && !end_flag_sent
+            && !send_audio(&out, round, transport, seq, AUDIO_FRAME_FLAG_END, &[]).await
+        {
+            error!("Failed to send end frame");
+            cancel.cancel();
+            break;
+        }
+        last_delivered.store(chat_response.index as i64, Ordering::SeqCst);
+        if chat_response.is_end {
+            if let Some(timings) = &timings {
+                timings.mark(TurnStage::LastAudioSent);
+            }
+        }
+
+        // 最后一条消息的最后一个音频块
+        if chat_response.is_end
+            && !send_event(&out, round, ServerMessage::AudioOutputFinished).await
+        {
+            error!("Failed to send finish signal");
+        }
+        finished |= chat_response.is_end;
+    }
+
+    // 生成中途出错时 Chat 直接关闭 channel，不会有 is_end
+    if !finished && !cancel.is_cancelled() {
+        let msg = "Failed to generate reply";
+        send_error(&out, round, ErrorCode::ChatFailed, msg).await;
+    }
+
+    drop(queue);
+    if let Err(e) = producer.await {
+        error!("TTS producer task failed: {}", e);
+    }
+    // 被打断时只保存已经合成的部分
+    if let Some(recording) = recording {
+        recording.save_output(TTS_BACKEND.sample_rate());
+    }
+}
+
+/// 一轮回复完整播出后上报各阶段耗时，被打断的轮次不算
+async fn report_turn_metrics(
+    app_state: &AppState,
+    out: &mpsc::Sender<Message>,
+    protocol_version: u32,
+    session_id: String,
+    responder: &Responder,
+) {
+    if !responder.timings.is_marked(TurnStage::LastAudioSent) {
+        return;
+    }
+    let turn_metrics = responder.timings.metrics();
+    debug!("Round {} metrics: {:?}", responder.round, turn_metrics);
+    metrics::observe_turn(&turn_metrics);
+
+    // 写库放到阻塞线程里，不占用读取客户端消息的循环
+    let db_pool = app_state.db_pool.clone();
+    let saved_metrics = turn_metrics.clone();
+    tokio::task::spawn_blocking(move || {
+        let saved = db_pool
+            .get()
+            .map_err(anyhow::Error::from)
+            .and_then(|mut conn| {
+                metrics::save_turn_metrics(&mut conn, &session_id, &saved_metrics)
+            });
+        if let Err(e) = saved {
+            error!("Failed to save turn metrics: {}", e);
+        }
+    });
+    send_versioned_event(
+        out,
+        responder.round,
+        protocol_version,
+        ServerMessage::TurnMetrics(turn_metrics),
+    )
+    .await;
+}
+
+/// 打断当前回复：停止 TTS、取消剩余的生成，并告诉客户端最后完整播出的是哪一句
+async fn interrupt(responder: &mut Option<Responder>, out: &mpsc::Sender<Message>) -> bool {
+    let responder = match responder.take() {
+        Some(responder) => responder,
+        None => return false,
+    };
+
+    responder.cancel.cancel();
+    if let Err(e) = responder.handle.await {
+        error!("Responder task failed: {}", e);
+    }
+
+    let last_index = responder.last_delivered.load(Ordering::SeqCst);
+    info!(
+        "Reply of round {} interrupted after sentence {}",
+        responder.round, last_index
+    );
+    send_event(
+        out,
+        responder.round,
+        ServerMessage::AudioOutputInterrupted { last_index },
+    )
+    .await;
+    true
+}
+
+// 处理 WebSocket 连接：一个连接上可以进行多轮对话，每轮是一次识别加一次回复
+async fn handle_socket(socket: WebSocket, app_state: AppState, user: CurrentUser) {
     debug!("WebSocket connection established");
-    let mut session_started = false;
-    let mut asr: Option<VolcanoEchoMage> = None;
-    let role_id: Option<String> = None;
+    metrics::WS_CONNECTIONS.inc();
+
+    // 读写分离：回复在后台任务中推送，主循环始终在读取客户端消息，才能及时响应打断
+    let (mut ws_sender, mut ws_receiver) = socket.split();
+    let (out, mut out_rx) = mpsc::channel::<Message>(64);
+    let writer = tokio::spawn(async move {
+        while let Some(msg) = out_rx.recv().await {
+            if let Err(e) = ws_sender.send(msg).await {
+                error!("Failed to send message: {}", e);
+                break;
+            }
+        }
+    });
+
+    let idle_timeout = idle_timeout();
+    let mut idle_at = Instant::now() + idle_timeout;
+    // start_session 之后才有识别配置
+    let mut asr_config: Option<AsrConfig> = None;
+    let mut output = OutputSettings {
+        protocol_version: MIN_PROTOCOL_VERSION,
+        transport: AudioTransport::Base64,
+        tts_config: app_state.global_config.tts_config.clone(),
+        format: None,
+        tts_lookahead: tts_lookahead(),
+        recording: None,
+        timings: None,
+    };
+    let mut vad: Option<Vad> = None;
+    let mut preroll: Vec<u8> = Vec::new();
+    // 本轮用户说的话，开启录音时才保存
+    let mut input_audio: Vec<u8> = Vec::new();
+    let mut round: u32 = 0;
+    let mut asr: Option<Box<dyn AsrSession>> = None;
+    // 本轮识别的中间结果
+    let mut asr_partials: Option<mpsc::Receiver<String>> = None;
+    let mut input_decoder: Option<OpusPacketDecoder> = None;
+    let mut responder: Option<Responder> = None;
+    let session_id = Arc::new(Mutex::new(String::new()));
+    let mut role_id = DEFAULT_ROLE_ID.to_string();
     //let mut audio_buffer = Vec::new();
 
-    while let Some(msg) = socket.recv().await {
+    loop {
+        let responding = responder.is_some();
+        let msg = tokio::select! {
+            msg = ws_receiver.next() => msg,
+            _ = async { (&mut responder.as_mut().unwrap().handle).await }, if responding => {
+                // 本轮回复已经全部推送完
+                if let Some(finished) = responder.take() {
+                    let session_id = session_id.lock().unwrap().clone();
+                    report_turn_metrics(
+                        &app_state,
+                        &out,
+                        output.protocol_version,
+                        session_id,
+                        &finished,
+                    )
+                    .await;
+                }
+                idle_at = Instant::now() + idle_timeout;
+                continue;
+            }
+            partial = async { asr_partials.as_mut().unwrap().recv().await }, if asr_partials.is_some() => {
+                match partial {
+                    Some(text) => {
+                        let asr_partial = ServerMessage::AsrPartial { text };
+                        send_versioned_event(&out, round, output.protocol_version, asr_partial).await;
+                    }
+                    None => asr_partials = None,
+                }
+                continue;
+            }
+            // 回复进行中不算空闲
+            _ = tokio::time::sleep_until(idle_at), if !responding => {
+                info!("WebSocket idle for {:?}, closing", idle_timeout);
+                break;
+            }
+        };
+        idle_at = Instant::now() + idle_timeout;
+
         let msg = match msg {
-            Ok(msg) => msg,
-            Err(e) => {
+            Some(Ok(msg)) => msg,
+            Some(Err(e)) => {
                 error!("Failed to receive message: {}", e);
                 break;
             }
+            None => break,
         };
 
-        if let Message::Text(text) = msg {
-            let ws_msg: WebSocketMessage = match serde_json::from_str(&text) {
-                Ok(msg) => msg,
+        let ws_msg = match msg {
+            Message::Text(text) => match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(msg) => Incoming::Message(msg),
                 Err(e) => {
                     error!("Failed to parse message: {}", e);
+                    send_error(&out, round, ErrorCode::InvalidMessage, &e.to_string()).await;
                     continue;
                 }
-            };
-
-            match ws_msg.msg_type.as_str() {
-                START_SESSION_MSG => {
-                    if let Ok(payload) =
-                        serde_json::from_value::<StartSessionPayload>(ws_msg.payload.unwrap())
-                    {
-                        info!("Starting session: {:?}", payload);
-
-                        // 初始化 VolcanoEchoMage
-                        let config = VolcanoConfig {
-                            //app_id: "7900512007".to_string(),
-                            app_id: "3806621263".to_string(),
-                            token: "wfm_Jdp4wR0CcQsPXZRQ-GfAMmGnyB0p".to_string(),
-                            //token: "y3uH1UFivyu4q6gKnwKKIA3snC3FXiXb".to_string(),
-                            cluster: "volcengine_streaming_common".to_string(),
-                            audio_format: "raw".to_string(),
-                            codec: "raw".to_string(),
-                            workflow: "audio_in,resample,partition,vad,fe,decode".to_string(),
-                            sample_rate: payload.sample_rate,
-                        };
-
-                        let mut volcano_asr = VolcanoEchoMage::new(config);
-                        if let Err(e) = volcano_asr.start().await {
-                            error!("Failed to start ASR: {}", e);
-                            socket
-                                .send(Message::Text(
-                                    json!({
-                                        "code": -1,
-                                        "msg": "Failed to start ASR"
-                                    })
-                                    .to_string()
-                                    .into(),
-                                ))
-                                .await
-                                .unwrap();
-                            return;
-                        }
+            },
+            Message::Binary(frame) => {
+                if output.transport != AudioTransport::Binary {
+                    let msg = "Binary audio was not negotiated";
+                    send_error(&out, round, ErrorCode::InvalidMessage, msg).await;
+                    continue;
+                }
+                match AudioFrameHeader::decode(&frame) {
+                    Some((header, audio)) if header.kind == AUDIO_FRAME_KIND_INPUT => {
+                        Incoming::Audio(audio.to_vec())
+                    }
+                    _ => {
+                        let msg = "Invalid binary audio frame";
+                        send_error(&out, round, ErrorCode::InvalidMessage, msg).await;
+                        continue;
+                    }
+                }
+            }
+            Message::Close(_) => break,
+            _ => continue,
+        };
 
-                        asr = Some(volcano_asr);
-                        session_started = true;
+        let mut finish_turn = false;
+        match ws_msg {
+            Incoming::Message(ClientMessage::StartSession(payload)) => {
+                info!("Starting session: {:?}", payload);
 
-                        let started = json!({
-                            "type": "session_started"
-                        });
+                let protocol_version = match negotiate_version(payload.protocol_version) {
+                    Some(version) => version,
+                    None => {
+                        let msg = format!(
+                            "Protocol version {} is not supported, expected {} to {}",
+                            payload.protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+                        );
+                        send_error(
+                            &out,
+                            payload.round,
+                            ErrorCode::UnsupportedProtocolVersion,
+                            &msg,
+                        )
+                        .await;
+                        break;
+                    }
+                };
 
-                        if let Err(e) = socket.send(Message::Text(started.to_string().into())).await
-                        {
-                            error!("Failed to send session_started: {}", e);
-                            break;
+                let role = match session_role(&app_state, &user.user_id, payload.role_id.clone()) {
+                    Ok(Some(role)) => role,
+                    Ok(None) => {
+                        let msg = "Role not found";
+                        send_error(&out, payload.round, ErrorCode::RoleNotFound, msg).await;
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Failed to load role: {}", e);
+                        let msg = "Failed to load role";
+                        send_error(&out, payload.round, ErrorCode::RoleNotFound, msg).await;
+                        continue;
+                    }
+                };
+                let requested_session_id =
+                    match owned_session_id(&app_state, &user.user_id, payload.session_id.clone()) {
+                        Ok(requested_session_id) => requested_session_id,
+                        Err(e) => {
+                            error!("Failed to load session: {}", e);
+                            let msg = "Failed to load session";
+                            send_error(&out, payload.round, ErrorCode::InvalidMessage, msg).await;
+                            continue;
                         }
-                        debug!("Session started");
-                    } else {
-                        error!("Failed to parse start_session payload");
-                        socket
-                            .send(Message::Text(
-                                json!({
-                                    "code": -1,
-                                    "msg": "Failed to parse start_session payload"
-                                })
-                                .to_string()
-                                .into(),
-                            ))
-                            .await
-                            .unwrap();
-                        return;
+                    };
+                let output_format = match session_output_format(&payload) {
+                    Ok(output_format) => output_format,
+                    Err(msg) => {
+                        send_error(&out, payload.round, ErrorCode::InvalidMessage, &msg).await;
+                        continue;
                     }
+                };
+                let decoder = if is_opus_input(&payload.input_format) {
+                    match OpusPacketDecoder::new(payload.sample_rate) {
+                        Ok(decoder) => Some(decoder),
+                        Err(e) => {
+                            let msg = e.to_string();
+                            send_error(&out, payload.round, ErrorCode::InvalidMessage, &msg).await;
+                            continue;
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                // 重新开始时丢弃进行中的回复
+                if let Some(responder) = responder.take() {
+                    responder.cancel.cancel();
+                    let _ = responder.handle.await;
                 }
-                AUDIO_INPUT_CHUNK_MSG => {
-                    if !session_started {
-                        error!("Received audio chunk before session start");
+                let session_asr = session_asr_config(&app_state.global_config.asr_config, &payload);
+                match start_asr(&session_asr).await {
+                    Ok(mut session) => {
+                        asr_partials = session.take_partials();
+                        asr = Some(session);
+                    }
+                    Err(e) => {
+                        error!("Failed to start ASR: {}", e);
+                        send_error(
+                            &out,
+                            payload.round,
+                            ErrorCode::AsrUnavailable,
+                            "Failed to start ASR",
+                        )
+                        .await;
+                        break;
+                    }
+                }
+                asr_config = Some(session_asr);
+                input_decoder = decoder;
+                let transport = negotiate_transport(protocol_version, payload.audio_transport);
+                output = OutputSettings {
+                    protocol_version,
+                    transport,
+                    tts_config: session_tts_config(
+                        &app_state.global_config.tts_config,
+                        &payload,
+                        &role,
+                    ),
+                    format: output_format,
+                    tts_lookahead: output.tts_lookahead,
+                    recording: None,
+                    timings: None,
+                };
+                role_id = role.id;
+                round = payload.round;
+                vad = session_vad(&payload);
+                preroll.clear();
+                input_audio.clear();
+                let turn_detection = match vad {
+                    Some(_) => TurnDetection::ServerVad,
+                    None => TurnDetection::Manual,
+                };
+                *session_id.lock().unwrap() = requested_session_id;
+
+                let started = ServerMessage::SessionStarted {
+                    protocol_version,
+                    audio_transport: transport,
+                    turn_detection,
+                };
+                if !send_event(&out, round, started).await {
+                    error!("Failed to send session_started");
+                    break;
+                }
+                debug!(
+                    "Session started with protocol version {}, {:?} audio",
+                    protocol_version, transport
+                );
+            }
+            Incoming::Message(ClientMessage::Interrupt) => {
+                if asr_config.is_none() {
+                    let msg = "Send start_session first";
+                    send_error(&out, round, ErrorCode::SessionNotStarted, msg).await;
+                    continue;
+                }
+
+                // 用户主动打断，下一段音频开始新一轮识别
+                interrupt(&mut responder, &out).await;
+            }
+            Incoming::Audio(_) | Incoming::Message(ClientMessage::AudioInputChunk(_)) => {
+                let session_asr = match asr_config.as_ref() {
+                    Some(session_asr) => session_asr,
+                    None => {
+                        error!("Received audio before session start");
+                        send_error(
+                            &out,
+                            round,
+                            ErrorCode::SessionNotStarted,
+                            "Send start_session first",
+                        )
+                        .await;
                         continue;
                     }
+                };
 
-                    if let Some(Value::String(audio_data)) = ws_msg.payload {
-                        if let Ok(decoded) = BASE64.decode(audio_data.as_bytes()) {
-                            if let Some(asr) = &mut asr {
-                                if let Err(e) = asr.send_audio(&decoded).await {
-                                    error!("Failed to send audio to ASR: {}", e);
-                                    continue;
-                                }
-                                //audio_buffer.extend_from_slice(&decoded);
+                let decoded = match ws_msg {
+                    Incoming::Audio(audio) => audio,
+                    Incoming::Message(ClientMessage::AudioInputChunk(audio_data)) => {
+                        match BASE64.decode(audio_data.as_bytes()) {
+                            Ok(decoded) => decoded,
+                            Err(e) => {
+                                let msg = e.to_string();
+                                send_error(&out, round, ErrorCode::InvalidMessage, &msg).await;
+                                continue;
                             }
                         }
                     }
+                    _ => continue,
+                };
+                // opus 输入每条消息是一个包，解码后和 PCM 输入走同样的流程
+                let decoded = match input_decoder.as_mut() {
+                    Some(decoder) => match decoder.decode(&decoded) {
+                        Ok(pcm) => pcm,
+                        Err(e) => {
+                            let msg = format!("Invalid opus packet: {}", e);
+                            send_error(&out, round, ErrorCode::InvalidMessage, &msg).await;
+                            continue;
+                        }
+                    },
+                    None => decoded,
+                };
+
+                match vad.as_mut() {
+                    // 回复期间用户开口，结束当前回复并开始新一轮识别
+                    None => {
+                        interrupt(&mut responder, &out).await;
+                    }
+                    // 服务端 VAD 模式下设备一直在发音频，只有检测到说话才算新一轮，静音不会打断回复
+                    Some(vad) => {
+                        let events = vad.process(&decoded);
+                        let speech_started = events.contains(&VadEvent::SpeechStarted);
+                        if asr.is_none() && !speech_started {
+                            // 还没开口，只保留最近一小段，开口后补给识别避免丢掉第一个字
+                            preroll.extend_from_slice(&decoded);
+                            let limit = session_asr.sample_rate as usize * 2 * PREROLL_MS / 1000;
+                            let excess = preroll.len().saturating_sub(limit);
+                            preroll.drain(..excess);
+                            continue;
+                        }
+                        // 说够 min_speech_ms 才打断，咳嗽、敲击这类短促的声音不会打断回复
+                        if events.contains(&VadEvent::BargeIn) {
+                            interrupt(&mut responder, &out).await;
+                        }
+                        finish_turn = events.contains(&VadEvent::SpeechEnded);
+                    }
                 }
-                AUDIO_INPUT_FINISH_MSG => {
-                    if let Some(asr) = &mut asr {
-                        match asr.receive_result().await {
-                            Ok(text) => {
-                                info!("ASR Result: {}", text);
-
-                                // 创建Chat实例并处理文本
-                                let mut chat = Chat::new(
-                                    "default_user".to_string(),
-                                    "".to_string(),
-                                    role_id.unwrap_or("default_role".to_string()),
-                                    app_state.db_pool.clone(),
-                                );
-
-                                match chat.on_recv_message(text).await {
-                                    Ok(mut chat_receiver) => {
-                                        debug!("chat_receiver ready to recv for tts");
-                                        // 从Chat的receiver中接收消息并进行TTS转换
-                                        loop {
-                                            debug!("receiver ready to recv for tts and enter loop");
-                                            let chat_response = chat_receiver.recv().await;
-                                            if chat_response.is_none() {
-                                                debug!("chat_response is none");
-                                                break;
-                                            }
-                                            let chat_response = chat_response.unwrap();
-                                            debug!("chat_response: {:?}", chat_response.split_text);
-                                            let tts_config = TTSConfig {
-                                                app_id: "7900512007".to_string(),
-                                                token: "y3uH1UFivyu4q6gKnwKKIA3snC3FXiXb"
-                                                    .to_string(),
-                                                cluster: "volcano_icl".to_string(),
-                                                voice_type: "S_TfBFm6r41".to_string(),
-                                                enc_format: "pcm".to_string(),
-                                            };
-                                            let mut tts = VolcWsTTS::new(tts_config);
-                                            if let Err(e) =
-                                                tts.init(&chat_response.split_text).await
-                                            {
-                                                error!("Failed to init TTS: {}", e);
-                                                continue;
-                                            }
-
-                                            match tts.stream_synthesize().await {
-                                                Ok(mut tts_receiver) => {
-                                                    // 处理TTS的音频流
-                                                    while let Some(synth_response) =
-                                                        tts_receiver.recv().await
-                                                    {
-                                                        if !synth_response.audio.is_empty() {
-                                                            // 将音频数据转换为base64
-                                                            let base64_audio = BASE64
-                                                                .encode(&synth_response.audio);
-
-                                                            // 发送音频数据到websocket客户端
-                                                            if let Err(e) = socket.send(Message::Text(json!({
-                                                                "type": "audio_output_chunk",
-                                                                "payload": base64_audio
-                                                            }).to_string().into())).await {
-                                                                error!("Failed to send audio chunk: {}", e);
-                                                                break;
-                                                            }
-                                                        }
-
-                                                        // 如果是最后一个音频块，并且是最后一条消息
-                                                        if synth_response.is_last
-                                                            && chat_response.is_end
-                                                        {
-                                                            if let Err(e) = socket
-                                                                .send(Message::Text(json!({
-                                                                    "type": "audio_output_finished"
-                                                                }).to_string().into()))
-                                                                .await
-                                                            {
-                                                                error!("Failed to send finish signal: {}", e);
-                                                            }
-                                                            break;
-                                                        }
-                                                    }
-                                                }
-                                                Err(e) => {
-                                                    error!("Failed to synthesize speech: {}", e);
-                                                }
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("Failed to process chat: {}", e);
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                error!("Failed to get ASR result: {}", e);
-                            }
+
+                // 上一轮的识别已经结束，这是新一轮的第一段音频
+                if asr.is_none() {
+                    match start_asr(session_asr).await {
+                        Ok(mut session) => {
+                            asr_partials = session.take_partials();
+                            asr = Some(session);
+                        }
+                        Err(e) => {
+                            error!("Failed to restart ASR: {}", e);
+                            send_error(
+                                &out,
+                                round + 1,
+                                ErrorCode::AsrUnavailable,
+                                "Failed to start ASR",
+                            )
+                            .await;
+                            break;
                         }
                     }
+                    round += 1;
+                    input_audio.clear();
+                    debug!("Round {} started", round);
+                }
 
-                    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-                    break;
+                if let Some(asr) = &mut asr {
+                    // 识别用的是 PCM 时才能录下来
+                    if RECORDING_STORAGE.is_some() && session_asr.audio_format == "raw" {
+                        input_audio.extend_from_slice(&preroll);
+                        input_audio.extend_from_slice(&decoded);
+                    }
+                    if !preroll.is_empty() {
+                        if let Err(e) = asr.send_audio(&preroll).await {
+                            error!("Failed to send audio to ASR: {}", e);
+                        }
+                        preroll.clear();
+                    }
+                    if let Err(e) = asr.send_audio(&decoded).await {
+                        error!("Failed to send audio to ASR: {}", e);
+                        continue;
+                    }
+                    //audio_buffer.extend_from_slice(&decoded);
                 }
-                _ => {
-                    error!("Unknown message type: {}", ws_msg.msg_type);
+
+                if finish_turn {
+                    debug!("VAD ended round {}", round);
+                    let speech_ended = ServerMessage::SpeechEnded;
+                    send_versioned_event(&out, round, output.protocol_version, speech_ended).await;
                 }
             }
+            Incoming::Message(ClientMessage::AudioInputFinish) => finish_turn = true,
+            Incoming::Message(ClientMessage::EndSession) => {
+                info!("Client ended the session");
+                break;
+            }
         }
+
+        // 本轮说话结束：客户端发了 audio_input_finish，或者服务端 VAD 检测到说完了
+        if !finish_turn {
+            continue;
+        }
+
+        let mut current_asr = match asr.take() {
+            Some(asr) => asr,
+            None => continue,
+        };
+        // 说完之后的中间结果没有意义了，以 asr_final 为准
+        asr_partials = None;
+        let timings = Arc::new(TurnTimings::new());
+        timings.mark(TurnStage::SpeechEnded);
+
+        let text = match current_asr.finish().await {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Failed to get ASR result: {}", e);
+                send_error(
+                    &out,
+                    round,
+                    ErrorCode::RecognitionFailed,
+                    "Failed to recognize speech",
+                )
+                .await;
+                continue;
+            }
+        };
+        timings.mark(TurnStage::AsrFinal);
+        info!("ASR Result of round {}: {}", round, text);
+        let asr_final = ServerMessage::AsrFinal { text: text.clone() };
+        send_versioned_event(&out, round, output.protocol_version, asr_final).await;
+
+        // 上一轮还没播完 (比如客户端没等打断就发了 audio_input_finish)，先停掉，两轮回复不能交错
+        interrupt(&mut responder, &out).await;
+
+        let cancel = CancellationToken::new();
+        // 创建Chat实例并处理文本
+        let mut chat = Chat::new(
+            user.user_id.clone(),
+            session_id.lock().unwrap().clone(),
+            role_id.clone(),
+            app_state.db_pool.clone(),
+        )
+        .with_cancel_token(cancel.clone())
+        .with_timings(timings.clone());
+
+        // 录音的 key 要写进 section，所以先定好 section_id
+        let recording = RECORDING_STORAGE.as_ref().map(|_| {
+            let recording = TurnRecording::new(&user.user_id, utils::gen_new_id());
+            if let Some(session_asr) = &asr_config {
+                recording.set_input(std::mem::take(&mut input_audio), session_asr.sample_rate);
+            }
+            recording
+        });
+        if let Some(recording) = &recording {
+            chat = chat.with_recording(recording.clone());
+        }
+
+        let chat_receiver = match chat.on_recv_message(text).await {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                error!("Failed to process chat: {}", e);
+                let msg = "Failed to generate reply";
+                send_error(&out, round, ErrorCode::ChatFailed, msg).await;
+                continue;
+            }
+        };
+
+        let last_delivered = Arc::new(AtomicI64::new(-1));
+        let handle = tokio::spawn(respond(
+            out.clone(),
+            chat_receiver,
+            round,
+            OutputSettings {
+                recording,
+                timings: Some(timings.clone()),
+                ..output.clone()
+            },
+            cancel.clone(),
+            last_delivered.clone(),
+            session_id.clone(),
+        ));
+        responder = Some(Responder {
+            round,
+            cancel,
+            handle,
+            last_delivered,
+            timings,
+        });
     }
 
+    // 连接结束时取消还在进行的回复
+    if let Some(responder) = responder.take() {
+        responder.cancel.cancel();
+        let _ = responder.handle.await;
+    }
+    drop(out);
+    let _ = writer.await;
+    metrics::WS_CONNECTIONS.dec();
     info!("WebSocket connection closed");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary_output() -> OutputSettings {
+        OutputSettings {
+            protocol_version: PROTOCOL_VERSION,
+            transport: AudioTransport::Binary,
+            tts_config: TtsConfig::default(),
+            format: None,
+            tts_lookahead: 2,
+            recording: None,
+            timings: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_end_frame_after_empty_last_sentence() {
+        let (chat_sender, chat_receiver) = mpsc::channel(4);
+        // Chat 的最后一句是标点后剩下的部分，通常是空的
+        chat_sender
+            .send(ChatResponse {
+                session_id: "session".to_string(),
+                index: 0,
+                split_text: String::new(),
+                is_end: true,
+            })
+            .await
+            .unwrap();
+        drop(chat_sender);
+
+        let (out, mut out_rx) = mpsc::channel(16);
+        let last_delivered = Arc::new(AtomicI64::new(-1));
+        respond(
+            out,
+            chat_receiver,
+            7,
+            binary_output(),
+            CancellationToken::new(),
+            last_delivered.clone(),
+            Arc::new(Mutex::new(String::new())),
+        )
+        .await;
+
+        let mut messages = Vec::new();
+        while let Some(message) = out_rx.recv().await {
+            messages.push(message);
+        }
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(&messages[0], Message::Text(text) if text.contains("assistant_text")));
+        let Message::Binary(frame) = &messages[1] else {
+            panic!("expected an end frame, got {:?}", messages[1]);
+        };
+        let (header, audio) = AudioFrameHeader::decode(frame).unwrap();
+        assert_eq!(header.kind, AUDIO_FRAME_KIND_OUTPUT);
+        assert_eq!(header.flags & AUDIO_FRAME_FLAG_END, AUDIO_FRAME_FLAG_END);
+        assert_eq!(header.stream_id, 7);
+        assert!(audio.is_empty());
+        assert!(
+            matches!(&messages[2], Message::Text(text) if text.contains("audio_output_finished"))
+        );
+        assert_eq!(last_delivered.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,123 @@
+use std::time::SystemTime;
+
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::StatusCode,
+    Extension,
+};
+use diesel::prelude::*;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+
+use crate::constant::MAX_MEMORY_LEN;
+use crate::models::{schema::user_memories, user_memory::UserMemory};
+use crate::structures::memory::{
+    ListMemoriesQuery, MemoryInfo, MemoryListResponse, UpdateMemoryRequest,
+};
+use crate::structures::user::CurrentUser;
+use crate::structures::{AppState, CommonResponse};
+use crate::utils;
+
+/// 列出当前用户的长期记忆，可按角色过滤
+pub async fn list_memories(
+    State(state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Query(query): Query<ListMemoriesQuery>,
+) -> Result<Json<MemoryListResponse>, StatusCode> {
+    let conn = &mut state
+        .db_pool
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut statement = user_memories::table
+        .filter(user_memories::user_id.eq(&user.user_id))
+        .into_boxed();
+    if let Some(role_id) = &query.role_id {
+        statement = statement.filter(user_memories::role_id.eq(role_id));
+    }
+
+    let results = statement
+        .order(user_memories::updated_at.desc())
+        .select(UserMemory::as_select())
+        .load(conn)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let memories = results
+        .into_iter()
+        .map(|m| MemoryInfo {
+            id: m.id,
+            role_id: m.role_id,
+            content: m.content,
+            updated_at: utils::to_unix_secs(m.updated_at),
+        })
+        .collect();
+
+    Ok(Json(MemoryListResponse {
+        code: 0,
+        msg: "ok".to_string(),
+        memories,
+    }))
+}
+
+pub async fn update_memory(
+    State(state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Path(memory_id): Path<String>,
+    Json(payload): Json<UpdateMemoryRequest>,
+) -> Result<Json<CommonResponse>, StatusCode> {
+    let content = payload.content.trim();
+    if content.is_empty() || content.chars().count() > MAX_MEMORY_LEN {
+        return Ok(Json(CommonResponse::error("Invalid memory content")));
+    }
+
+    let conn = &mut state
+        .db_pool
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let updated = diesel::update(
+        user_memories::table
+            .filter(user_memories::id.eq(&memory_id))
+            .filter(user_memories::user_id.eq(&user.user_id)),
+    )
+    .set((
+        user_memories::content.eq(content),
+        user_memories::updated_at.eq(SystemTime::now()),
+    ))
+    .execute(conn)
+    .map_err(|e| match e {
+        // 和同一角色下已有的记忆重复
+        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => StatusCode::CONFLICT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    })?;
+
+    if updated == 0 {
+        return Ok(Json(CommonResponse::error("Memory not found")));
+    }
+
+    Ok(Json(CommonResponse::success()))
+}
+
+pub async fn delete_memory(
+    State(state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Path(memory_id): Path<String>,
+) -> Result<Json<CommonResponse>, StatusCode> {
+    let conn = &mut state
+        .db_pool
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let deleted = diesel::delete(
+        user_memories::table
+            .filter(user_memories::id.eq(&memory_id))
+            .filter(user_memories::user_id.eq(&user.user_id)),
+    )
+    .execute(conn)
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    if deleted == 0 {
+        return Ok(Json(CommonResponse::error("Memory not found")));
+    }
+
+    Ok(Json(CommonResponse::success()))
+}
@@ -0,0 +1,43 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::error;
+use tokio::time::Instant;
+
+use crate::services::metrics::{self, HTTP_REQUESTS_TOTAL, HTTP_REQUEST_SECONDS};
+use crate::structures::AppState;
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Prometheus 文本格式的指标
+pub async fn metrics(State(state): State<AppState>) -> Result<impl IntoResponse, StatusCode> {
+    let body = metrics::render(&state.db_pool).map_err(|e| {
+        error!("Failed to render metrics: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(([(header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)], body))
+}
+
+/// 按路由模板统计请求数和耗时，用 route_layer 挂上才能拿到 MatchedPath
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = req.method().to_string();
+    let start = Instant::now();
+
+    let response = next.run(req).await;
+
+    HTTP_REQUEST_SECONDS
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    HTTP_REQUESTS_TOTAL
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    response
+}
@@ -4,4 +4,9 @@ pub mod auth;
 pub use auth::*;
 pub mod echo_mage;
 pub use echo_mage::*;
-pub mod chat;
\ No newline at end of file
+pub mod chat;
+pub mod account;
+pub mod device;
+pub mod memory;
+pub mod metrics;
+pub mod recording;
@@ -0,0 +1,60 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+use diesel::prelude::*;
+use log::error;
+
+use crate::models::schema::{sections, sessions};
+use crate::services::recording::RECORDING_STORAGE;
+use crate::structures::user::CurrentUser;
+use crate::structures::AppState;
+
+/// 下载一轮对话的录音，kind 是 input (用户说的话) 或 output (合成的回复)
+pub async fn download_recording(
+    State(state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Path((section_id, kind)): Path<(String, String)>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let storage = RECORDING_STORAGE.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    let conn = &mut state
+        .db_pool
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // 只能下载自己会话里的录音
+    let statement = sections::table
+        .filter(sections::section_id.eq(&section_id))
+        .filter(
+            sections::session_id.eq_any(
+                sessions::table
+                    .filter(sessions::user_id.eq(&user.user_id))
+                    .select(sessions::session_id),
+            ),
+        );
+    let key = match kind.as_str() {
+        "input" => statement
+            .select(sections::input_audio_path)
+            .first::<Option<String>>(conn),
+        "output" => statement
+            .select(sections::output_audio_path)
+            .first::<Option<String>>(conn),
+        _ => return Err(StatusCode::NOT_FOUND),
+    }
+    .optional()
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .flatten()
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let data = storage
+        .get(&key)
+        .await
+        .map_err(|e| {
+            error!("Failed to read recording {}: {}", key, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(([(header::CONTENT_TYPE, "audio/wav")], data))
+}
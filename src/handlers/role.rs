@@ -5,14 +5,17 @@ use axum::{
     http::{HeaderMap, StatusCode},
     Extension,
 };
-use diesel::{SelectableHelper as _};
+use log::info;
 
+use crate::constant::{DEFAULT_ROLE_ID, MAX_CONTEXT_TOKENS, MIN_CONTEXT_TOKENS};
 use crate::models::{
-    role::Role,
+    role::{Role, RoleChangeset},
     schema::{self, user_role},
 };
 use crate::structures::user::CurrentUser;
-use crate::structures::{AppState, CommonResponse, RoleInfo, RoleResponse, SwitchRoleRequest};
+use crate::structures::{
+    AppState, CommonResponse, RoleInfo, RoleResponse, SwitchRoleRequest, UpdateRoleRequest,
+};
 use crate::{
     structures::{CreateRolePayload, CreateRoleRequest, CreateRoleResponse},
 };
@@ -23,6 +26,56 @@ use diesel::SelectableHelper;
 
 const DEVICE_ID_HEADER: &str = "X-OZ-Device-ID";
 
+/// 用户通过 switch_role 选中的角色
+pub fn current_role_id(conn: &mut PgConnection, user_id: &str) -> QueryResult<Option<String>> {
+    user_role::table
+        .filter(user_role::id.eq(user_id))
+        .select(user_role::role_id)
+        .first::<String>(conn)
+        .optional()
+}
+
+/// 默认角色和用户自己创建的角色才能使用
+fn role_accessible(conn: &mut PgConnection, user_id: &str, role_id: &str) -> QueryResult<bool> {
+    let count = schema::roles::table
+        .filter(schema::roles::id.eq(role_id))
+        .filter(
+            schema::roles::is_default
+                .eq(true)
+                .or(schema::roles::created_by.eq(user_id)),
+        )
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(count > 0)
+}
+
+/// 请求里指定的角色优先，其次是用户选中的角色，都没有时用默认角色。
+/// 别人创建的角色当作没有指定
+pub fn resolve_role_id(
+    conn: &mut PgConnection,
+    user_id: &str,
+    requested: Option<String>,
+) -> QueryResult<String> {
+    if let Some(role_id) = requested.filter(|role_id| !role_id.is_empty()) {
+        if role_accessible(conn, user_id, &role_id)? {
+            return Ok(role_id);
+        }
+        info!("Role {} is not accessible to user {}", role_id, user_id);
+    }
+    match current_role_id(conn, user_id)? {
+        Some(role_id) if role_accessible(conn, user_id, &role_id)? => Ok(role_id),
+        _ => Ok(DEFAULT_ROLE_ID.to_string()),
+    }
+}
+
+pub fn find_role(conn: &mut PgConnection, role_id: &str) -> QueryResult<Option<Role>> {
+    schema::roles::table
+        .filter(schema::roles::id.eq(role_id))
+        .select(Role::as_select())
+        .first(conn)
+        .optional()
+}
+
 pub async fn get_roles(
     State(state): State<AppState>,
     Extension(user): Extension<CurrentUser>,
@@ -115,8 +168,13 @@ pub async fn create_role(
         .get()
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
+    let generation = payload.generation.unwrap_or_default();
+    if let Err(msg) = generation.validate() {
+        return Ok(Json(CreateRoleResponse::error(&msg)));
+    }
+
     // 创建新角色
-    let role = Role {
+    let mut role = Role {
         id: xid::new().to_string(),
         is_default: false,
         created_by: user.user_id,
@@ -127,7 +185,16 @@ pub async fn create_role(
         prompt: payload.prompt,
         created_at: SystemTime::now(),
         updated_at: SystemTime::now(),
+        model: None,
+        temperature: None,
+        top_p: None,
+        max_tokens: None,
+        stop_sequences: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+        context_token_budget: None,
     };
+    role.set_generation_params(generation.clone());
 
     // 插入数据库
     match diesel::insert_into(schema::roles::table)
@@ -144,9 +211,84 @@ pub async fn create_role(
                 my_story: payload.my_story,
                 voice_id: role.voice_id,
                 preference: payload.preference,
+                generation,
             };
             Ok(Json(CreateRoleResponse::success(response_payload)))
         }
         Err(_) => Ok(Json(CreateRoleResponse::error("Failed to create role"))),
     }
 }
+
+pub async fn update_role(
+    State(state): State<AppState>,
+    Extension(user): Extension<CurrentUser>,
+    Json(payload): Json<UpdateRoleRequest>,
+) -> Result<Json<CommonResponse>, StatusCode> {
+    if let Some(generation) = &payload.generation {
+        if let Err(msg) = generation.validate() {
+            return Ok(Json(CommonResponse::error(&msg)));
+        }
+    }
+    if let Some(budget) = payload.context_token_budget {
+        if !(MIN_CONTEXT_TOKENS..=MAX_CONTEXT_TOKENS).contains(&budget) {
+            return Ok(Json(CommonResponse::error(&format!(
+                "context_token_budget must be between {} and {}",
+                MIN_CONTEXT_TOKENS, MAX_CONTEXT_TOKENS
+            ))));
+        }
+    }
+
+    let conn = &mut state
+        .db_pool
+        .get()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let mut role = match schema::roles::table
+        .filter(schema::roles::id.eq(&payload.role_id))
+        .select(Role::as_select())
+        .first(conn)
+        .optional()
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    {
+        Some(role) => role,
+        None => return Ok(Json(CommonResponse::error("Role not found"))),
+    };
+
+    // 只有角色的创建者可以修改，默认角色不允许修改
+    if role.is_default || role.created_by != user.user_id {
+        return Ok(Json(CommonResponse::error("Permission denied")));
+    }
+
+    let changeset = RoleChangeset {
+        name: payload.name,
+        prompt: payload.prompt,
+        voice_id: payload.voice_id,
+        context_token_budget: payload.context_token_budget.map(|budget| budget as i32),
+        updated_at: SystemTime::now(),
+    };
+
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        diesel::update(schema::roles::table.find(&role.id))
+            .set(&changeset)
+            .execute(conn)?;
+
+        if let Some(generation) = payload.generation {
+            role.set_generation_params(generation);
+            diesel::update(schema::roles::table.find(&role.id))
+                .set((
+                    schema::roles::model.eq(&role.model),
+                    schema::roles::temperature.eq(role.temperature),
+                    schema::roles::top_p.eq(role.top_p),
+                    schema::roles::max_tokens.eq(role.max_tokens),
+                    schema::roles::stop_sequences.eq(&role.stop_sequences),
+                    schema::roles::presence_penalty.eq(role.presence_penalty),
+                    schema::roles::frequency_penalty.eq(role.frequency_penalty),
+                ))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(CommonResponse::success()))
+}
@@ -1,11 +1,14 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ChatRequest {
     pub message: String,
+    /// 为空时新建会话
+    #[serde(default)]
     pub session_id: String,
-    pub user_id: String,
-    pub role_id: String,
+    /// 为空时使用用户当前切换到的角色
+    #[serde(default)]
+    pub role_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
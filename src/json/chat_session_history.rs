@@ -15,6 +15,7 @@ pub struct History {
     pub id: String,
     pub user: String,
     pub assistant: String,
+    pub is_partial: bool,
 }
 
 
@@ -4,4 +4,4 @@ pub mod chat_history_response;
 pub mod chat_session_history;
 pub mod role;
 pub mod mqtt;
-
+pub mod ws;
@@ -1,7 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+use crate::services::llm::GenerationParams;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AddRoleRequest {
     pub name: String,
     pub prompt: String,
+    #[serde(default)]
+    pub generation: Option<GenerationParams>,
 }
@@ -0,0 +1,410 @@
+//! 语音 WebSocket 的消息协议。文本帧都是 `{"type": ..., "payload": ...}` 形式的 JSON，
+//! 服务端消息额外带上 `round`。可以用 `cargo run --bin ws_schema` 导出 JSON Schema。
+//!
+//! 协议版本 2 起音频可以走二进制帧：8 字节头 (kind u8, flags u8, stream_id u16, seq u32，
+//! 大端) 后面直接跟音频数据。stream_id 是轮次的低 16 位，seq 是该轮内的帧序号。
+//!
+//! 协议版本 3 起服务端会发出识别结果和回复文字 (asr_partial、asr_final、assistant_text)，
+//! 以及服务端 VAD 的 speech_ended 和每轮的 turn_metrics，协商出的版本更低时不发这些事件，
+//! 旧客户端不会收到不认识的 type。
+//!
+//! input_format/output_format 为 opus 时每条音频消息 (或二进制帧) 是一个 Opus 包；
+//! output_format 为 ogg_opus 时每句话是一个 Ogg Opus 流，按顺序拼接音频消息即可播放。
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// 服务端当前的协议版本
+pub const PROTOCOL_VERSION: u32 = 3;
+/// 服务端仍然兼容的最低协议版本
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+/// 支持二进制音频帧的最低协议版本
+pub const BINARY_AUDIO_MIN_VERSION: u32 = 2;
+/// 支持 asr_partial、asr_final、assistant_text 事件的最低协议版本
+pub const TRANSCRIPT_EVENTS_MIN_VERSION: u32 = 3;
+/// 支持 speech_ended 事件的最低协议版本，更低的版本照样可以用服务端 VAD，只是收不到通知
+pub const SPEECH_ENDED_MIN_VERSION: u32 = 3;
+/// 支持 turn_metrics 事件的最低协议版本
+pub const TURN_METRICS_MIN_VERSION: u32 = 3;
+
+pub const AUDIO_FRAME_HEADER_LEN: usize = 8;
+pub const AUDIO_FRAME_KIND_INPUT: u8 = 1;
+pub const AUDIO_FRAME_KIND_OUTPUT: u8 = 2;
+/// 本轮回复的最后一帧音频，最后一句没有音频时是一个空帧
+pub const AUDIO_FRAME_FLAG_END: u8 = 0x01;
+
+fn default_protocol_version() -> u32 {
+    MIN_PROTOCOL_VERSION
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct StartSessionPayload {
+    /// 客户端支持的最高协议版本，不传视为 1
+    #[serde(default = "default_protocol_version")]
+    pub protocol_version: u32,
+    /// 继续已有的聊天会话，为空时第一轮回复会新建会话
+    #[serde(default)]
+    pub session_id: String,
+    /// 使用的角色，不传时用用户通过 /api/role/switch 选中的角色
+    #[serde(default)]
+    pub role_id: Option<String>,
+    pub input_format: String,
+    pub output_format: String,
+    pub sample_rate: u32,
+    /// 为 0 时使用 TTS 的原始采样率
+    pub output_sample_rate: u32,
+    /// pcm/wav 输出的声道数，不传为 1
+    #[serde(default)]
+    pub output_channels: Option<u16>,
+    /// pcm/wav 输出的采样位宽 (8/16/24/32)，不传为 16
+    #[serde(default)]
+    pub output_bits_per_sample: Option<u16>,
+    /// 起始轮次，断线重连时客户端可以接着之前的轮次
+    #[serde(default)]
+    pub round: u32,
+    /// 希望使用的音频传输方式，协议版本不够时服务端会退回 base64
+    #[serde(default)]
+    pub audio_transport: AudioTransport,
+    /// 一轮说话怎么结束，server_vad 只支持 pcm 输入
+    #[serde(default)]
+    pub turn_detection: TurnDetection,
+    /// server_vad 模式下说话后静音多久结束一轮，不传使用服务端配置
+    #[serde(default)]
+    pub vad_silence_ms: Option<u64>,
+    /// server_vad 模式下短于这个时长的声音不算说话，不传使用服务端配置
+    #[serde(default)]
+    pub vad_min_speech_ms: Option<u64>,
+    /// opus/ogg_opus 输出每个包的时长 (5/10/20/40/60)，不传使用服务端配置
+    #[serde(default)]
+    pub opus_frame_ms: Option<u32>,
+    /// opus/ogg_opus 输出预期的丢包率，大于 0 时开启 FEC，不传使用服务端配置
+    #[serde(default)]
+    pub opus_packet_loss_percent: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TurnDetection {
+    /// 客户端发送 audio_input_finish 结束一轮
+    #[default]
+    Manual,
+    /// 服务端检测到说话结束后自动结束一轮，协议版本 3 起会发出 speech_ended
+    ServerVad,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioTransport {
+    /// 音频 base64 编码后放在 JSON 文本帧中
+    #[default]
+    Base64,
+    /// 音频放在带帧头的二进制帧中
+    Binary,
+}
+
+/// 客户端发给服务端的消息
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum ClientMessage {
+    StartSession(StartSessionPayload),
+    /// base64 编码的音频
+    AudioInputChunk(String),
+    AudioInputFinish,
+    /// 打断正在播放的回复
+    Interrupt,
+    EndSession,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// 消息不是合法 JSON，或者类型、字段不对
+    InvalidMessage,
+    UnsupportedProtocolVersion,
+    SessionNotStarted,
+    AsrUnavailable,
+    RecognitionFailed,
+    RoleNotFound,
+    /// 生成回复失败，本轮没有 (完整的) 回复
+    ChatFailed,
+}
+
+/// 服务端发给客户端的消息
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum ServerMessage {
+    SessionStarted {
+        /// 协商后的协议版本
+        protocol_version: u32,
+        /// 实际使用的音频传输方式
+        audio_transport: AudioTransport,
+        /// 实际使用的结束方式
+        turn_detection: TurnDetection,
+    },
+    /// 服务端 VAD 检测到用户说完了，本轮不需要再发 audio_input_finish
+    SpeechEnded,
+    /// 识别中间结果，后面的会覆盖前面的
+    AsrPartial {
+        text: String,
+    },
+    /// 本轮识别的最终结果
+    AsrFinal {
+        text: String,
+    },
+    /// 回复的一句文字，在这一句的音频之前发出
+    AssistantText {
+        /// 句子序号，和 audio_output_interrupted 的 last_index 对应
+        index: usize,
+        text: String,
+        is_end: bool,
+    },
+    /// base64 编码的音频
+    AudioOutputChunk(String),
+    AudioOutputFinished,
+    AudioOutputInterrupted {
+        /// 完整播出的最后一句的序号，-1 表示一句都没有
+        last_index: i64,
+    },
+    /// 一轮回复完整播出后的各阶段耗时，被打断的轮次没有
+    TurnMetrics(TurnMetrics),
+    Error {
+        code: ErrorCode,
+        msg: String,
+    },
+}
+
+/// 一轮语音对话各阶段的耗时 (毫秒)，没有经过的阶段为空
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TurnMetrics {
+    /// 说完到识别出最终结果
+    pub asr_ms: Option<u64>,
+    /// 识别结果到 LLM 的第一个 token
+    pub llm_first_token_ms: Option<u64>,
+    /// 识别结果到第一句话分出来
+    pub first_sentence_ms: Option<u64>,
+    /// 第一句话到 TTS 的第一段音频
+    pub tts_first_byte_ms: Option<u64>,
+    /// 说完到第一段音频，用户感受到的延迟
+    pub first_audio_ms: Option<u64>,
+    /// 说完到最后一段音频发出
+    pub total_ms: Option<u64>,
+}
+
+impl ServerMessage {
+    /// 客户端至少要协商到这个版本才能收到这条消息
+    pub fn min_protocol_version(&self) -> u32 {
+        match self {
+            ServerMessage::AsrPartial { .. }
+            | ServerMessage::AsrFinal { .. }
+            | ServerMessage::AssistantText { .. } => TRANSCRIPT_EVENTS_MIN_VERSION,
+            ServerMessage::SpeechEnded => SPEECH_ENDED_MIN_VERSION,
+            ServerMessage::TurnMetrics(_) => TURN_METRICS_MIN_VERSION,
+            _ => MIN_PROTOCOL_VERSION,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ServerEvent {
+    #[serde(flatten)]
+    pub message: ServerMessage,
+    pub round: u32,
+}
+
+/// 取双方都支持的最高版本，客户端版本太旧时返回 None
+pub fn negotiate_version(client_version: u32) -> Option<u32> {
+    if client_version < MIN_PROTOCOL_VERSION {
+        return None;
+    }
+    Some(client_version.min(PROTOCOL_VERSION))
+}
+
+/// 协商音频传输方式，旧版本协议只能用 base64
+pub fn negotiate_transport(protocol_version: u32, requested: AudioTransport) -> AudioTransport {
+    if protocol_version < BINARY_AUDIO_MIN_VERSION {
+        return AudioTransport::Base64;
+    }
+    requested
+}
+
+/// 二进制音频帧的帧头
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioFrameHeader {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u16,
+    pub seq: u32,
+}
+
+impl AudioFrameHeader {
+    /// 帧头加音频数据组成一个二进制帧
+    pub fn encode(&self, audio: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(AUDIO_FRAME_HEADER_LEN + audio.len());
+        frame.push(self.kind);
+        frame.push(self.flags);
+        frame.extend_from_slice(&self.stream_id.to_be_bytes());
+        frame.extend_from_slice(&self.seq.to_be_bytes());
+        frame.extend_from_slice(audio);
+        frame
+    }
+
+    /// 拆出帧头和音频数据，长度不够时返回 None
+    pub fn decode(frame: &[u8]) -> Option<(Self, &[u8])> {
+        if frame.len() < AUDIO_FRAME_HEADER_LEN {
+            return None;
+        }
+        let header = Self {
+            kind: frame[0],
+            flags: frame[1],
+            stream_id: u16::from_be_bytes([frame[2], frame[3]]),
+            seq: u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]),
+        };
+        Some((header, &frame[AUDIO_FRAME_HEADER_LEN..]))
+    }
+}
+
+/// 协议的 JSON Schema，给固件和 app 生成客户端代码用
+pub fn protocol_schema() -> serde_json::Value {
+    serde_json::json!({
+        "protocol_version": PROTOCOL_VERSION,
+        "min_protocol_version": MIN_PROTOCOL_VERSION,
+        "binary_audio_frame": {
+            "min_protocol_version": BINARY_AUDIO_MIN_VERSION,
+            "header_len": AUDIO_FRAME_HEADER_LEN,
+            "byte_order": "big_endian",
+            "fields": ["kind:u8", "flags:u8", "stream_id:u16", "seq:u32"],
+            "kinds": { "audio_input": AUDIO_FRAME_KIND_INPUT, "audio_output": AUDIO_FRAME_KIND_OUTPUT },
+            "flags": { "end": AUDIO_FRAME_FLAG_END },
+        },
+        "server_event_min_protocol_version": {
+            "asr_partial": TRANSCRIPT_EVENTS_MIN_VERSION,
+            "asr_final": TRANSCRIPT_EVENTS_MIN_VERSION,
+            "assistant_text": TRANSCRIPT_EVENTS_MIN_VERSION,
+            "speech_ended": SPEECH_ENDED_MIN_VERSION,
+            "turn_metrics": TURN_METRICS_MIN_VERSION,
+        },
+        "client_message": schemars::schema_for!(ClientMessage),
+        "server_event": schemars::schema_for!(ServerEvent),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_client_message_format() {
+        let msg: ClientMessage = serde_json::from_value(json!({
+            "type": "start_session",
+            "payload": {
+                "input_format": "pcm",
+                "output_format": "pcm",
+                "sample_rate": 16000,
+                "output_sample_rate": 24000
+            }
+        }))
+        .unwrap();
+        match msg {
+            ClientMessage::StartSession(payload) => {
+                assert_eq!(payload.protocol_version, 1);
+                assert_eq!(payload.round, 0);
+                assert_eq!(payload.audio_transport, AudioTransport::Base64);
+                assert_eq!(payload.turn_detection, TurnDetection::Manual);
+            }
+            _ => panic!("unexpected message"),
+        }
+
+        let msg: ClientMessage =
+            serde_json::from_value(json!({ "type": "audio_input_finish" })).unwrap();
+        assert!(matches!(msg, ClientMessage::AudioInputFinish));
+
+        assert!(serde_json::from_value::<ClientMessage>(json!({ "type": "unknown" })).is_err());
+    }
+
+    #[test]
+    fn test_server_event_format() {
+        let event = ServerEvent {
+            message: ServerMessage::Error {
+                code: ErrorCode::SessionNotStarted,
+                msg: "start_session first".to_string(),
+            },
+            round: 2,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({
+                "type": "error",
+                "payload": { "code": "session_not_started", "msg": "start_session first" },
+                "round": 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        assert_eq!(negotiate_version(0), None);
+        assert_eq!(negotiate_version(1), Some(1));
+        assert_eq!(
+            negotiate_version(PROTOCOL_VERSION + 1),
+            Some(PROTOCOL_VERSION)
+        );
+    }
+
+    #[test]
+    fn test_min_protocol_version() {
+        let asr_final = ServerMessage::AsrFinal {
+            text: "你好".to_string(),
+        };
+        assert_eq!(
+            asr_final.min_protocol_version(),
+            TRANSCRIPT_EVENTS_MIN_VERSION
+        );
+        assert!(asr_final.min_protocol_version() > BINARY_AUDIO_MIN_VERSION);
+        assert_eq!(
+            ServerMessage::SpeechEnded.min_protocol_version(),
+            SPEECH_ENDED_MIN_VERSION
+        );
+        assert_eq!(
+            ServerMessage::TurnMetrics(TurnMetrics::default()).min_protocol_version(),
+            TURN_METRICS_MIN_VERSION
+        );
+        assert_eq!(
+            ServerMessage::AudioOutputFinished.min_protocol_version(),
+            MIN_PROTOCOL_VERSION
+        );
+    }
+
+    #[test]
+    fn test_negotiate_transport() {
+        assert_eq!(
+            negotiate_transport(1, AudioTransport::Binary),
+            AudioTransport::Base64
+        );
+        assert_eq!(
+            negotiate_transport(2, AudioTransport::Binary),
+            AudioTransport::Binary
+        );
+        assert_eq!(
+            negotiate_transport(2, AudioTransport::Base64),
+            AudioTransport::Base64
+        );
+    }
+
+    #[test]
+    fn test_audio_frame_roundtrip() {
+        let header = AudioFrameHeader {
+            kind: AUDIO_FRAME_KIND_OUTPUT,
+            flags: AUDIO_FRAME_FLAG_END,
+            stream_id: 0x0102,
+            seq: 0x03040506,
+        };
+        let frame = header.encode(&[9, 9]);
+        assert_eq!(frame, vec![2, 1, 1, 2, 3, 4, 5, 6, 9, 9]);
+
+        let (decoded, audio) = AudioFrameHeader::decode(&frame).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(audio, &[9, 9]);
+        assert!(AudioFrameHeader::decode(&frame[..7]).is_none());
+    }
+}
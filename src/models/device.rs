@@ -0,0 +1,17 @@
+use std::time::SystemTime;
+
+use crate::models::schema;
+use diesel::prelude::*;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::devices)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Device {
+    pub id: String,
+    pub user_id: Option<String>, // 绑定的用户，未绑定时为空
+    pub name: String,
+    pub secret_hash: Option<String>,
+    pub paired_at: Option<SystemTime>,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
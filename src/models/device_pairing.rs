@@ -0,0 +1,17 @@
+use std::time::SystemTime;
+
+use crate::models::schema;
+use diesel::prelude::*;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::device_pairings)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DevicePairing {
+    pub id: String,
+    pub code: String,
+    pub device_id: String,
+    pub pairing_secret: String, // 只有发起配对的设备持有，用于轮询配对结果
+    pub confirmed_by: Option<String>,
+    pub expires_at: SystemTime,
+    pub created_at: SystemTime,
+}
@@ -1,7 +1,12 @@
+pub mod device;
+pub mod device_pairing;
+pub mod refresh_token;
 pub mod role;
 pub mod section;
 pub mod session;
 pub mod schema;
+pub mod user;
+pub mod user_memory;
 pub mod user_role;
 use diesel::prelude::*;
 
@@ -0,0 +1,17 @@
+use std::time::SystemTime;
+
+use crate::models::schema;
+use diesel::prelude::*;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::refresh_tokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct RefreshToken {
+    pub id: String, // 即 refresh token 的 jti
+    pub user_id: String,
+    pub device_id: Option<String>,
+    pub revoked: bool,
+    pub expires_at: SystemTime,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
@@ -1,6 +1,8 @@
 use std::time::SystemTime;
 
+use crate::constant::MAX_TOKENS;
 use crate::models::schema;
+use crate::services::llm::GenerationParams;
 //use chrono::{DateTime, Utc};
 use diesel::prelude::*;
 
@@ -18,4 +20,52 @@ pub struct Role {
     pub prompt: String,
     pub created_at: SystemTime,
     pub updated_at: SystemTime,
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<i32>,
+    pub stop_sequences: Option<Vec<String>>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    /// 上下文 (system prompt + 历史 + 新消息) 的 token 预算，为空时使用全局配置
+    pub context_token_budget: Option<i32>,
+}
+
+#[derive(AsChangeset)]
+#[diesel(table_name = schema::roles)]
+pub struct RoleChangeset {
+    pub name: Option<String>,
+    pub prompt: Option<String>,
+    pub voice_id: Option<String>,
+    pub context_token_budget: Option<i32>,
+    pub updated_at: SystemTime,
+}
+
+impl Role {
+    /// 角色的生成参数，未设置 max_tokens 时使用全局默认值
+    pub fn generation_params(&self) -> GenerationParams {
+        GenerationParams {
+            model: self.model.clone(),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            max_tokens: Some(
+                self.max_tokens
+                    .map(|max_tokens| max_tokens as u32)
+                    .unwrap_or(MAX_TOKENS),
+            ),
+            stop: self.stop_sequences.clone(),
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+        }
+    }
+
+    pub fn set_generation_params(&mut self, params: GenerationParams) {
+        self.model = params.model;
+        self.temperature = params.temperature;
+        self.top_p = params.top_p;
+        self.max_tokens = params.max_tokens.map(|max_tokens| max_tokens as i32);
+        self.stop_sequences = params.stop;
+        self.presence_penalty = params.presence_penalty;
+        self.frequency_penalty = params.frequency_penalty;
+    }
 }
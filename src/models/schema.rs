@@ -1,5 +1,41 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    device_pairings (id) {
+        id -> Varchar,
+        code -> Varchar,
+        device_id -> Varchar,
+        pairing_secret -> Varchar,
+        confirmed_by -> Nullable<Varchar>,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    devices (id) {
+        id -> Varchar,
+        user_id -> Nullable<Varchar>,
+        name -> Text,
+        secret_hash -> Nullable<Text>,
+        paired_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (id) {
+        id -> Varchar,
+        user_id -> Varchar,
+        device_id -> Nullable<Varchar>,
+        revoked -> Bool,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     roles (id) {
         id -> Varchar,
@@ -12,6 +48,14 @@ diesel::table! {
         prompt -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        model -> Nullable<Text>,
+        temperature -> Nullable<Float4>,
+        top_p -> Nullable<Float4>,
+        max_tokens -> Nullable<Int4>,
+        stop_sequences -> Nullable<Array<Text>>,
+        presence_penalty -> Nullable<Float4>,
+        frequency_penalty -> Nullable<Float4>,
+        context_token_budget -> Nullable<Int4>,
     }
 }
 
@@ -23,6 +67,9 @@ diesel::table! {
         assistant_message -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        is_partial -> Bool,
+        input_audio_path -> Nullable<Varchar>,
+        output_audio_path -> Nullable<Varchar>,
     }
 }
 
@@ -34,6 +81,22 @@ diesel::table! {
         title -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        summary -> Nullable<Text>,
+        summarized_until -> Nullable<Timestamp>,
+        summary_updated_at -> Nullable<Timestamp>,
+        last_turn_metrics -> Nullable<Text>,
+        memories -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    user_memories (id) {
+        id -> Varchar,
+        user_id -> Varchar,
+        role_id -> Varchar,
+        content -> Text,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -51,13 +114,24 @@ diesel::table! {
         id -> Varchar,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        username -> Varchar,
+        password_hash -> Text,
     }
 }
 
+diesel::joinable!(device_pairings -> devices (device_id));
+diesel::joinable!(device_pairings -> users (confirmed_by));
+diesel::joinable!(devices -> users (user_id));
+diesel::joinable!(refresh_tokens -> users (user_id));
+
 diesel::allow_tables_to_appear_in_same_query!(
+    device_pairings,
+    devices,
+    refresh_tokens,
     roles,
     sections,
     sessions,
+    user_memories,
     user_role,
     users,
 );
@@ -14,4 +14,9 @@ pub struct Section {
     pub assistant_message: String,
     pub created_at: SystemTime,
     pub updated_at: SystemTime,
+    /// 生成被中断时只保存了部分回复
+    pub is_partial: bool,
+    /// 语音对话的录音在存储中的 key，未开启录音时为空
+    pub input_audio_path: Option<String>,
+    pub output_audio_path: Option<String>,
 }
@@ -13,4 +13,12 @@ pub struct Session {
     pub role_id: String,
     pub created_at: SystemTime,
     pub updated_at: SystemTime,
+    /// 较早对话的滚动摘要，覆盖 created_at <= summarized_until 的所有 section
+    pub summary: Option<String>,
+    pub summarized_until: Option<SystemTime>,
+    pub summary_updated_at: Option<SystemTime>,
+    /// 最近一轮语音对话各阶段耗时的 JSON
+    pub last_turn_metrics: Option<String>,
+    /// 会话开始时选出的长期记忆 (JSON 字符串数组)，整个会话沿用
+    pub memories: Option<String>,
 }
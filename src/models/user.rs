@@ -0,0 +1,15 @@
+use std::time::SystemTime;
+
+use crate::models::schema;
+use diesel::prelude::*;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::users)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    pub password_hash: String,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
@@ -0,0 +1,16 @@
+use std::time::SystemTime;
+
+use crate::models::schema;
+use diesel::prelude::*;
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::user_memories)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserMemory {
+    pub id: String,
+    pub user_id: String,
+    pub role_id: String,
+    pub content: String,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
@@ -0,0 +1,357 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use anyhow::Result;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus::{Application, Bitrate, Channels, Decoder, Encoder};
+use serde::Deserialize;
+
+use super::PcmFormat;
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::{DEFAULT_OPUS_FRAME_MS, OPUS_CONFIG};
+
+/// Opus 支持的采样率
+const OPUS_SAMPLE_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+/// 可以配置的帧时长，2.5ms 不是整数不支持
+const OPUS_FRAME_MS: [u32; 5] = [5, 10, 20, 40, 60];
+/// 一个包最长 120ms
+const MAX_PACKET_MS: usize = 120;
+/// 推荐的输出缓冲大小
+const MAX_PACKET_BYTES: usize = 4000;
+/// Ogg Opus 的 granule 固定按 48kHz 计
+const OGG_OPUS_RATE: u64 = 48000;
+/// 编码器在 48kHz 下的延迟，解码时要丢掉这么多采样
+const OGG_OPUS_PRE_SKIP: u64 = 312;
+const OGG_OPUS_VENDOR: &str = "oz_server";
+
+// 每个 Ogg 流要有不同的序列号，客户端才能把多个流拼在一起播放
+static NEXT_OGG_SERIAL: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct OpusConfig {
+    /// 每个包的时长
+    #[serde(default = "default_frame_ms")]
+    pub frame_ms: u32,
+    /// 目标码率 (bit/s)，为空时由编码器决定
+    #[serde(default)]
+    pub bitrate: Option<i32>,
+    /// 预期的丢包率，大于 0 时开启带内 FEC，丢包时解码端可以恢复上一个包
+    #[serde(default)]
+    pub packet_loss_percent: u8,
+}
+
+fn default_frame_ms() -> u32 {
+    DEFAULT_OPUS_FRAME_MS
+}
+
+impl Default for OpusConfig {
+    fn default() -> Self {
+        Self {
+            frame_ms: default_frame_ms(),
+            bitrate: None,
+            packet_loss_percent: 0,
+        }
+    }
+}
+
+impl OpusConfig {
+    pub fn load() -> Self {
+        OZ_SERVER_CONFIG
+            .get::<OpusConfig>(OPUS_CONFIG)
+            .unwrap_or_default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !OPUS_FRAME_MS.contains(&self.frame_ms) {
+            return Err(format!("opus frame_ms must be one of {:?}", OPUS_FRAME_MS));
+        }
+        if self.packet_loss_percent > 100 {
+            return Err("opus packet_loss_percent must be between 0 and 100".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Opus 只支持固定的几种采样率和最多两个声道
+pub fn check_format(sample_rate: u32, channels: u16) -> Result<(), String> {
+    if !OPUS_SAMPLE_RATES.contains(&sample_rate) {
+        return Err(format!(
+            "opus sample_rate must be one of {:?}",
+            OPUS_SAMPLE_RATES
+        ));
+    }
+    if channels == 0 || channels > 2 {
+        return Err("opus supports 1 or 2 channels".to_string());
+    }
+    Ok(())
+}
+
+fn opus_channels(channels: u16) -> Channels {
+    if channels == 2 {
+        Channels::Stereo
+    } else {
+        Channels::Mono
+    }
+}
+
+/// 设备上行的 Opus 包解码成 16 位单声道 PCM，每条消息是一个包
+pub struct OpusPacketDecoder {
+    decoder: Decoder,
+    buffer: Vec<i16>,
+}
+
+impl OpusPacketDecoder {
+    pub fn new(sample_rate: u32) -> Result<Self> {
+        check_format(sample_rate, 1).map_err(anyhow::Error::msg)?;
+        Ok(Self {
+            decoder: Decoder::new(sample_rate, Channels::Mono)?,
+            buffer: vec![0; sample_rate as usize * MAX_PACKET_MS / 1000],
+        })
+    }
+
+    pub fn decode(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        let samples = self.decoder.decode(packet, &mut self.buffer, false)?;
+        Ok(self.buffer[..samples]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect())
+    }
+}
+
+/// 把 PCM 切成固定时长的帧编码成 Opus 包，不足一帧的留到下一次
+pub struct OpusFrameEncoder {
+    encoder: Encoder,
+    format: PcmFormat,
+    config: OpusConfig,
+    /// 每帧的采样数 (所有声道)
+    frame_len: usize,
+    pending: Vec<i16>,
+    /// 已经收到的每声道采样数
+    received: u64,
+    /// 已经编码的每声道采样数 (包括最后补的静音)
+    encoded: u64,
+}
+
+impl OpusFrameEncoder {
+    pub fn new(format: PcmFormat, config: OpusConfig) -> Result<Self> {
+        check_format(format.sample_rate, format.channels).map_err(anyhow::Error::msg)?;
+        config.validate().map_err(anyhow::Error::msg)?;
+
+        let mut encoder = Encoder::new(
+            format.sample_rate,
+            opus_channels(format.channels),
+            Application::Voip,
+        )?;
+        if let Some(bitrate) = config.bitrate {
+            encoder.set_bitrate(Bitrate::Bits(bitrate))?;
+        }
+        if config.packet_loss_percent > 0 {
+            encoder.set_inband_fec(true)?;
+            encoder.set_packet_loss_perc(config.packet_loss_percent as i32)?;
+        }
+
+        let frame_len =
+            (format.sample_rate * config.frame_ms / 1000) as usize * format.channels as usize;
+        Ok(Self {
+            encoder,
+            format,
+            config,
+            frame_len,
+            pending: Vec::new(),
+            received: 0,
+            encoded: 0,
+        })
+    }
+
+    /// 编码所有完整的帧，samples 是交错存放的归一化采样
+    pub fn push(&mut self, samples: &[f32]) -> Result<Vec<Vec<u8>>> {
+        self.received += (samples.len() / self.format.channels as usize) as u64;
+        self.pending.extend(
+            samples
+                .iter()
+                .map(|s| (s * 32768.0).clamp(-32768.0, 32767.0) as i16),
+        );
+        self.encode_frames()
+    }
+
+    /// 补静音把剩下的采样和编码器延迟都编码出去
+    pub fn finish(&mut self) -> Result<Vec<Vec<u8>>> {
+        let pre_skip = OGG_OPUS_PRE_SKIP * self.format.sample_rate as u64 / OGG_OPUS_RATE;
+        let channels = self.format.channels as usize;
+        let frame_samples = (self.frame_len / channels) as u64;
+        let needed = (self.received + pre_skip).div_ceil(frame_samples) * frame_samples;
+        let padding = needed.saturating_sub(self.encoded) as usize * channels;
+        self.pending.resize(padding, 0);
+        self.encode_frames()
+    }
+
+    fn encode_frames(&mut self) -> Result<Vec<Vec<u8>>> {
+        let mut packets = Vec::new();
+        while self.pending.len() >= self.frame_len {
+            let frame = self.pending.drain(..self.frame_len).collect::<Vec<i16>>();
+            packets.push(self.encoder.encode_vec(&frame, MAX_PACKET_BYTES)?);
+            self.encoded += (self.frame_len / self.format.channels as usize) as u64;
+        }
+        Ok(packets)
+    }
+
+    /// 折算成 48kHz 的采样数
+    fn to_ogg_rate(&self, samples: u64) -> u64 {
+        samples * OGG_OPUS_RATE / self.format.sample_rate as u64
+    }
+
+    pub fn frame_ms(&self) -> u32 {
+        self.config.frame_ms
+    }
+}
+
+/// 把 Opus 包封装成 Ogg 流。每次写入返回新产生的字节，客户端按顺序拼起来就是完整的 .opus 文件
+pub struct OggOpusWriter {
+    writer: PacketWriter<'static, Vec<u8>>,
+    serial: u32,
+    format: PcmFormat,
+    /// 已经写入的包对应的 48kHz 采样数
+    granule: u64,
+    started: bool,
+}
+
+impl OggOpusWriter {
+    pub fn new(format: PcmFormat) -> Self {
+        Self {
+            writer: PacketWriter::new(Vec::new()),
+            serial: NEXT_OGG_SERIAL.fetch_add(1, Ordering::Relaxed),
+            format,
+            granule: 0,
+            started: false,
+        }
+    }
+
+    fn write_headers(&mut self) -> Result<()> {
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(self.format.channels as u8);
+        head.extend_from_slice(&(OGG_OPUS_PRE_SKIP as u16).to_le_bytes());
+        head.extend_from_slice(&self.format.sample_rate.to_le_bytes());
+        // output gain 和 channel mapping family
+        head.extend_from_slice(&0i16.to_le_bytes());
+        head.push(0);
+        self.writer
+            .write_packet(head, self.serial, PacketWriteEndInfo::EndPage, 0)?;
+
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(OGG_OPUS_VENDOR.len() as u32).to_le_bytes());
+        tags.extend_from_slice(OGG_OPUS_VENDOR.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        self.writer
+            .write_packet(tags, self.serial, PacketWriteEndInfo::EndPage, 0)?;
+        Ok(())
+    }
+
+    /// 每个包单独成页，保证及时推送。end 时最后一页的 granule 去掉补的静音
+    pub fn write(
+        &mut self,
+        encoder: &OpusFrameEncoder,
+        packets: Vec<Vec<u8>>,
+        end: bool,
+    ) -> Result<Vec<u8>> {
+        if !self.started {
+            self.write_headers()?;
+            self.started = true;
+        }
+
+        let frame = OGG_OPUS_RATE * encoder.frame_ms() as u64 / 1000;
+        let count = packets.len();
+        for (i, packet) in packets.into_iter().enumerate() {
+            self.granule += frame;
+            let info = if end && i + 1 == count {
+                PacketWriteEndInfo::EndStream
+            } else {
+                PacketWriteEndInfo::EndPage
+            };
+            let granule = match info {
+                PacketWriteEndInfo::EndStream => self
+                    .granule
+                    .min(OGG_OPUS_PRE_SKIP + encoder.to_ogg_rate(encoder.received)),
+                _ => self.granule,
+            };
+            self.writer
+                .write_packet(packet, self.serial, info, granule)?;
+        }
+        if end && count == 0 {
+            self.writer.write_packet(
+                Vec::new(),
+                self.serial,
+                PacketWriteEndInfo::EndStream,
+                self.granule,
+            )?;
+        }
+        Ok(std::mem::take(self.writer.inner_mut()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(samples: usize) -> Vec<f32> {
+        (0..samples)
+            .map(|i| (i as f32 * 0.05).sin() * 0.3)
+            .collect()
+    }
+
+    #[test]
+    fn test_opus_round_trip() {
+        let format = PcmFormat::mono16(16000);
+        let mut encoder = OpusFrameEncoder::new(format, OpusConfig::default()).unwrap();
+        // 50ms 只够两个 20ms 帧，剩下的等 finish 补齐
+        let mut packets = encoder.push(&tone(800)).unwrap();
+        assert_eq!(packets.len(), 2);
+        packets.extend(encoder.finish().unwrap());
+        assert_eq!(packets.len(), 3);
+
+        let mut decoder = OpusPacketDecoder::new(16000).unwrap();
+        let pcm = packets
+            .iter()
+            .flat_map(|packet| decoder.decode(packet).unwrap())
+            .collect::<Vec<u8>>();
+        assert_eq!(pcm.len(), 3 * 320 * 2);
+    }
+
+    #[test]
+    fn test_ogg_stream_pages() {
+        let format = PcmFormat::mono16(24000);
+        let mut encoder = OpusFrameEncoder::new(format, OpusConfig::default()).unwrap();
+        let mut writer = OggOpusWriter::new(format);
+
+        let packets = encoder.push(&tone(480)).unwrap();
+        let mut stream = writer.write(&encoder, packets, false).unwrap();
+        assert!(stream.starts_with(b"OggS"));
+
+        let packets = encoder.finish().unwrap();
+        stream.extend(writer.write(&encoder, packets, true).unwrap());
+
+        let mut reader = ogg::reading::PacketReader::new(std::io::Cursor::new(stream));
+        let head = reader.read_packet_expected().unwrap();
+        assert!(head.data.starts_with(b"OpusHead"));
+        let tags = reader.read_packet_expected().unwrap();
+        assert!(tags.data.starts_with(b"OpusTags"));
+        let mut last = None;
+        while let Some(packet) = reader.read_packet().unwrap() {
+            last = Some(packet);
+        }
+        let last = last.unwrap();
+        assert!(last.last_in_stream());
+        // 20ms 的音频加上编码器延迟
+        assert_eq!(last.absgp_page(), OGG_OPUS_PRE_SKIP + 960);
+    }
+
+    #[test]
+    fn test_invalid_opus_format() {
+        assert!(check_format(44100, 1).is_err());
+        assert!(check_format(16000, 3).is_err());
+        let config = OpusConfig {
+            frame_ms: 25,
+            ..OpusConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
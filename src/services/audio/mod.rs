@@ -0,0 +1,416 @@
+pub mod codec;
+
+use std::io::Cursor;
+
+use anyhow::Result;
+
+use codec::{OggOpusWriter, OpusConfig, OpusFrameEncoder};
+
+/// 支持的整数采样位宽，8 位是无符号的，其余是有符号小端
+const SUPPORTED_BITS: [u16; 4] = [8, 16, 24, 32];
+const MAX_CHANNELS: u16 = 8;
+
+/// 交错存放的整数 PCM 的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+}
+
+impl PcmFormat {
+    /// 16 位单声道，ASR/TTS 后端普遍使用的格式
+    pub fn mono16(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            channels: 1,
+            bits_per_sample: 16,
+        }
+    }
+
+    fn bytes_per_sample(&self) -> usize {
+        self.bits_per_sample as usize / 8
+    }
+
+    fn bytes_per_frame(&self) -> usize {
+        self.bytes_per_sample() * self.channels as usize
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.sample_rate == 0 {
+            return Err("sample_rate must be positive".to_string());
+        }
+        if self.channels == 0 || self.channels > MAX_CHANNELS {
+            return Err(format!("channels must be between 1 and {}", MAX_CHANNELS));
+        }
+        if !SUPPORTED_BITS.contains(&self.bits_per_sample) {
+            return Err(format!(
+                "bits_per_sample must be one of {:?}",
+                SUPPORTED_BITS
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 推给客户端的音频封装
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    /// 裸 PCM
+    Pcm,
+    /// 每个音频块都是一个完整的 WAV 文件，设备收到就能直接播放
+    Wav,
+    /// 每个音频块是一个 Opus 包
+    Opus,
+    /// 每句话是一个 Ogg Opus 流，音频块按顺序拼起来就是 .opus 文件
+    OggOpus,
+}
+
+impl Container {
+    /// 服务端能转换出的格式，其余格式 (如 mp3) 交给 TTS 后端直接输出
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "pcm" | "raw" => Some(Container::Pcm),
+            "wav" => Some(Container::Wav),
+            "opus" => Some(Container::Opus),
+            "ogg" | "ogg_opus" => Some(Container::OggOpus),
+            _ => None,
+        }
+    }
+
+    pub fn is_opus(&self) -> bool {
+        matches!(self, Container::Opus | Container::OggOpus)
+    }
+}
+
+/// 客户端要求的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFormat {
+    pub container: Container,
+    pub pcm: PcmFormat,
+    /// 仅 opus 和 ogg_opus 使用
+    pub opus: OpusConfig,
+}
+
+/// 把 PCM 转成另一种采样率、声道数和位宽。可以分块输入，块之间保持连续
+pub struct PcmConverter {
+    input: PcmFormat,
+    output: PcmFormat,
+    /// 输入采样率 / 输出采样率
+    step: f64,
+    /// 下一个输出帧在 tail 开头的缓冲区中的位置
+    position: f64,
+    /// 上一块的最后一帧 (已经转换了声道)，线性插值跨块时需要
+    tail: Vec<f32>,
+    /// 上一块末尾不足一帧的字节
+    pending: Vec<u8>,
+}
+
+impl PcmConverter {
+    pub fn new(input: PcmFormat, output: PcmFormat) -> Self {
+        Self {
+            input,
+            output,
+            step: input.sample_rate as f64 / output.sample_rate as f64,
+            position: 0.0,
+            tail: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.input == self.output
+    }
+
+    pub fn convert(&mut self, bytes: &[u8]) -> Vec<u8> {
+        if self.is_passthrough() {
+            return bytes.to_vec();
+        }
+        let samples = self.convert_samples(bytes);
+        encode_samples(&samples, self.output.bits_per_sample)
+    }
+
+    /// 转换成输出格式的归一化采样，交错存放
+    fn convert_samples(&mut self, bytes: &[u8]) -> Vec<f32> {
+        self.pending.extend_from_slice(bytes);
+        let frame_len = self.input.bytes_per_frame();
+        let whole = self.pending.len() / frame_len * frame_len;
+        let decoded = decode_samples(&self.pending[..whole], self.input.bits_per_sample);
+        self.pending.drain(..whole);
+
+        let mixed = convert_channels(&decoded, self.input.channels, self.output.channels);
+        if self.input.sample_rate == self.output.sample_rate {
+            return mixed;
+        }
+        self.resample(mixed)
+    }
+
+    /// 线性插值重采样
+    fn resample(&mut self, samples: Vec<f32>) -> Vec<f32> {
+        let channels = self.output.channels as usize;
+        let mut buffer = std::mem::take(&mut self.tail);
+        buffer.extend(samples);
+        let frames = buffer.len() / channels;
+        if frames == 0 {
+            return Vec::new();
+        }
+
+        let mut out = Vec::new();
+        while self.position + 1.0 < frames as f64 {
+            let index = self.position as usize;
+            let frac = (self.position - index as f64) as f32;
+            for channel in 0..channels {
+                let a = buffer[index * channels + channel];
+                let b = buffer[(index + 1) * channels + channel];
+                out.push(a + (b - a) * frac);
+            }
+            self.position += self.step;
+        }
+
+        // 保留最后一帧，下一块从它开始插值
+        self.position -= (frames - 1) as f64;
+        self.tail = buffer[(frames - 1) * channels..].to_vec();
+        out
+    }
+}
+
+/// 把 TTS 输出的 PCM 编码成客户端要求的格式
+pub struct OutputEncoder {
+    container: Container,
+    format: PcmFormat,
+    converter: PcmConverter,
+    opus: Option<OpusFrameEncoder>,
+    ogg: Option<OggOpusWriter>,
+}
+
+impl OutputEncoder {
+    pub fn new(input: PcmFormat, output: OutputFormat) -> Result<Self> {
+        let opus = if output.container.is_opus() {
+            Some(OpusFrameEncoder::new(output.pcm, output.opus)?)
+        } else {
+            None
+        };
+        let ogg = match output.container {
+            Container::OggOpus => Some(OggOpusWriter::new(output.pcm)),
+            _ => None,
+        };
+        Ok(Self {
+            container: output.container,
+            format: output.pcm,
+            converter: PcmConverter::new(input, output.pcm),
+            opus,
+            ogg,
+        })
+    }
+
+    /// 编码一段 PCM，返回的每一项单独推给客户端，可能为空
+    pub fn encode(&mut self, pcm: &[u8]) -> Result<Vec<Vec<u8>>> {
+        match self.container {
+            Container::Pcm => Ok(vec![self.converter.convert(pcm)]),
+            Container::Wav => {
+                let samples = self.converter.convert_samples(pcm);
+                if samples.is_empty() {
+                    return Ok(Vec::new());
+                }
+                Ok(vec![wav_bytes(&samples, self.format)?])
+            }
+            Container::Opus | Container::OggOpus => {
+                let samples = self.converter.convert_samples(pcm);
+                let packets = self.opus_encoder().push(&samples)?;
+                self.wrap(packets, false)
+            }
+        }
+    }
+
+    /// 一段音频结束，输出编码器里剩下的数据
+    pub fn finish(&mut self) -> Result<Vec<Vec<u8>>> {
+        if !self.container.is_opus() {
+            return Ok(Vec::new());
+        }
+        let packets = self.opus_encoder().finish()?;
+        self.wrap(packets, true)
+    }
+
+    fn opus_encoder(&mut self) -> &mut OpusFrameEncoder {
+        self.opus.as_mut().expect("opus encoder for opus container")
+    }
+
+    fn wrap(&mut self, packets: Vec<Vec<u8>>, end: bool) -> Result<Vec<Vec<u8>>> {
+        match (self.ogg.as_mut(), self.opus.as_ref()) {
+            (Some(ogg), Some(opus)) => {
+                let pages = ogg.write(opus, packets, end)?;
+                Ok(if pages.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![pages]
+                })
+            }
+            _ => Ok(packets),
+        }
+    }
+}
+
+fn decode_samples(bytes: &[u8], bits_per_sample: u16) -> Vec<f32> {
+    match bits_per_sample {
+        8 => bytes.iter().map(|b| (*b as f32 - 128.0) / 128.0).collect(),
+        16 => bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect(),
+        24 => bytes
+            .chunks_exact(3)
+            .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8_388_608.0)
+            .collect(),
+        _ => bytes
+            .chunks_exact(4)
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2_147_483_648.0)
+            .collect(),
+    }
+}
+
+/// 归一化采样量化成有符号整数，和 decode_samples 的缩放一致
+fn quantize(sample: f32, bits_per_sample: u16) -> i32 {
+    let scale = (1i64 << (bits_per_sample - 1)) as f64;
+    (sample as f64 * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
+fn encode_samples(samples: &[f32], bits_per_sample: u16) -> Vec<u8> {
+    let width = bits_per_sample as usize / 8;
+    let mut out = Vec::with_capacity(samples.len() * width);
+    for sample in samples {
+        let value = quantize(*sample, bits_per_sample);
+        match bits_per_sample {
+            8 => out.push((value + 128) as u8),
+            _ => out.extend_from_slice(&value.to_le_bytes()[..width]),
+        }
+    }
+    out
+}
+
+/// 单声道复制到每个声道，多声道先混成单声道再分配
+fn convert_channels(samples: &[f32], from: u16, to: u16) -> Vec<f32> {
+    if from == to {
+        return samples.to_vec();
+    }
+    let from = from as usize;
+    let to = to as usize;
+    samples
+        .chunks_exact(from)
+        .flat_map(|frame| {
+            let mono = frame.iter().sum::<f32>() / from as f32;
+            std::iter::repeat_n(mono, to)
+        })
+        .collect()
+}
+
+/// 整段 PCM 写成一个 WAV 文件
+pub fn wav_from_pcm(pcm: &[u8], format: PcmFormat) -> Result<Vec<u8>> {
+    wav_bytes(&decode_samples(pcm, format.bits_per_sample), format)
+}
+
+fn wav_bytes(samples: &[f32], format: PcmFormat) -> Result<Vec<u8>> {
+    let spec = hound::WavSpec {
+        channels: format.channels,
+        sample_rate: format.sample_rate,
+        bits_per_sample: format.bits_per_sample,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut cursor = Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut cursor, spec)?;
+    for sample in samples {
+        writer.write_sample(quantize(*sample, format.bits_per_sample))?;
+    }
+    writer.finalize()?;
+    Ok(cursor.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm16(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_resample_keeps_duration_across_chunks() {
+        let input = PcmFormat::mono16(24000);
+        let output = PcmFormat::mono16(16000);
+        let audio = pcm16(
+            &(0..2400)
+                .map(|i| (i % 200) as i16 * 100)
+                .collect::<Vec<_>>(),
+        );
+
+        let mut whole = PcmConverter::new(input, output);
+        let expected = whole.convert(&audio);
+        // 100ms 的 24kHz 变成 16kHz，最后一帧留作插值
+        assert!((expected.len() as i64 / 2 - 1600).abs() <= 1);
+
+        // 分块 (包括不足一帧的奇数字节) 输入结果一样
+        let mut chunked = PcmConverter::new(input, output);
+        let mut streamed = Vec::new();
+        for chunk in audio.chunks(333) {
+            streamed.extend(chunked.convert(chunk));
+        }
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn test_channels_and_width() {
+        let input = PcmFormat::mono16(16000);
+        let output = PcmFormat {
+            sample_rate: 16000,
+            channels: 2,
+            bits_per_sample: 8,
+        };
+        let mut converter = PcmConverter::new(input, output);
+        let converted = converter.convert(&pcm16(&[0, 16384, -32768]));
+        assert_eq!(converted, vec![128, 128, 192, 192, 0, 0]);
+
+        let wide = PcmFormat {
+            bits_per_sample: 24,
+            ..input
+        };
+        let mut back = PcmConverter::new(wide, input);
+        let mut widened = PcmConverter::new(input, wide);
+        let audio = pcm16(&[1000, -1000, 32767]);
+        assert_eq!(back.convert(&widened.convert(&audio)), audio);
+    }
+
+    #[test]
+    fn test_wav_chunk_is_playable() {
+        let output = OutputFormat {
+            container: Container::Wav,
+            pcm: PcmFormat::mono16(8000),
+            opus: OpusConfig::default(),
+        };
+        let mut encoder = OutputEncoder::new(PcmFormat::mono16(8000), output).unwrap();
+        let mut chunks = encoder.encode(&pcm16(&[1, 2, 3, 4])).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert!(encoder.finish().unwrap().is_empty());
+        let wav = chunks.remove(0);
+
+        let mut reader = hound::WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().sample_rate, 8000);
+        let samples = reader
+            .samples::<i16>()
+            .map(|s| s.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(samples, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_invalid_format() {
+        assert!(PcmFormat::mono16(16000).validate().is_ok());
+        assert!(PcmFormat::mono16(0).validate().is_err());
+        let format = PcmFormat {
+            bits_per_sample: 12,
+            ..PcmFormat::mono16(16000)
+        };
+        assert!(format.validate().is_err());
+        assert_eq!(Container::parse("WAV"), Some(Container::Wav));
+        assert_eq!(Container::parse("ogg_opus"), Some(Container::OggOpus));
+        assert_eq!(Container::parse("mp3"), None);
+    }
+}
@@ -0,0 +1,304 @@
+use serde::Deserialize;
+
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::{CONTEXT_CONFIG, DEFAULT_CONTEXT_TOKENS, DEFAULT_HISTORY_FETCH_LIMIT};
+use crate::services::llm::LlmMessage;
+
+/// 每条消息除内容外的固定开销 (role、分隔符等)
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+/// 裁剪后一条消息至少保留的 token 数，再少就没有意义了，直接丢弃
+const MIN_TRIMMED_TOKENS: usize = 16;
+const TRIM_MARKER: &str = "…";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncateStrategy {
+    /// 放不下的最旧轮次整轮丢弃
+    #[default]
+    DropOldest,
+    /// 先裁剪最旧的一轮使其刚好放下，更旧的再丢弃
+    TrimOldest,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ContextConfig {
+    #[serde(default = "default_max_tokens")]
+    pub max_tokens: usize,
+    /// 每次最多从数据库读取的历史轮数
+    #[serde(default = "default_history_fetch_limit")]
+    pub history_fetch_limit: i64,
+    #[serde(default)]
+    pub strategy: TruncateStrategy,
+}
+
+fn default_max_tokens() -> usize {
+    DEFAULT_CONTEXT_TOKENS
+}
+
+fn default_history_fetch_limit() -> i64 {
+    DEFAULT_HISTORY_FETCH_LIMIT
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: default_max_tokens(),
+            history_fetch_limit: default_history_fetch_limit(),
+            strategy: TruncateStrategy::default(),
+        }
+    }
+}
+
+impl ContextConfig {
+    pub fn load() -> Self {
+        OZ_SERVER_CONFIG
+            .get::<ContextConfig>(CONTEXT_CONFIG)
+            .unwrap_or_default()
+    }
+}
+
+/// 一轮对话
+#[derive(Debug, Clone)]
+pub struct Turn {
+    pub user: String,
+    pub assistant: String,
+}
+
+#[derive(Debug)]
+pub struct ContextWindow {
+    pub messages: Vec<LlmMessage>,
+    pub used_tokens: usize,
+    /// 没有完整放进上下文的 (最旧的) 轮数，包括被裁剪的那一轮
+    pub dropped_turns: usize,
+    /// 是否有一轮被裁剪过
+    pub trimmed: bool,
+}
+
+/// 粗略估算 token 数：非 ASCII 字符 (中文等) 按一个字一个 token，ASCII 按四个字符一个 token
+pub fn estimate_tokens(text: &str) -> usize {
+    let mut ascii = 0usize;
+    let mut other = 0;
+    for c in text.chars() {
+        if c.is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+    }
+    other + ascii.div_ceil(4)
+}
+
+fn message_tokens(text: &str) -> usize {
+    estimate_tokens(text) + MESSAGE_OVERHEAD_TOKENS
+}
+
+fn turn_tokens(turn: &Turn) -> usize {
+    message_tokens(&turn.user) + message_tokens(&turn.assistant)
+}
+
+/// 保留文本末尾不超过 max_tokens 的部分
+fn trim_to_tokens(text: &str, max_tokens: usize) -> String {
+    if estimate_tokens(text) <= max_tokens {
+        return text.to_string();
+    }
+
+    let budget = max_tokens.saturating_sub(estimate_tokens(TRIM_MARKER));
+    let chars = text.chars().collect::<Vec<char>>();
+    // 从尾部往前累加，和 estimate_tokens 的算法一致，不用每次重新估算整段
+    let (mut ascii, mut other) = (0usize, 0usize);
+    let mut start = chars.len();
+    while start > 0 {
+        if chars[start - 1].is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+        if other + ascii.div_ceil(4) > budget {
+            break;
+        }
+        start -= 1;
+    }
+    format!(
+        "{}{}",
+        TRIM_MARKER,
+        chars[start..].iter().collect::<String>()
+    )
+}
+
+fn trim_turn(turn: &Turn, available: usize) -> Option<Turn> {
+    let content_budget = available.checked_sub(2 * MESSAGE_OVERHEAD_TOKENS)?;
+    if content_budget < 2 * MIN_TRIMMED_TOKENS {
+        return None;
+    }
+
+    // 用户消息通常较短，优先完整保留，剩下的给助手回复
+    let user_tokens = estimate_tokens(&turn.user).min(content_budget / 2);
+    let assistant_tokens = content_budget - user_tokens;
+    Some(Turn {
+        user: trim_to_tokens(&turn.user, user_tokens),
+        assistant: trim_to_tokens(&turn.assistant, assistant_tokens),
+    })
+}
+
+/// 组装 system prompt + 历史 + 新消息，历史按从旧到新排列。
+/// system prompt 和新消息总是保留，历史从最新的一轮开始往回填，直到用完预算。
+pub fn build_context(
+    system_prompt: &str,
+    history: &[Turn],
+    new_message: &str,
+    max_tokens: usize,
+    strategy: TruncateStrategy,
+) -> ContextWindow {
+    let mut used_tokens = message_tokens(system_prompt) + message_tokens(new_message);
+    let mut remaining = max_tokens.saturating_sub(used_tokens);
+
+    let mut included: Vec<Turn> = Vec::new();
+    let mut trimmed = false;
+    for turn in history.iter().rev() {
+        let cost = turn_tokens(turn);
+        if cost <= remaining {
+            remaining -= cost;
+            used_tokens += cost;
+            included.push(turn.clone());
+            continue;
+        }
+
+        if strategy == TruncateStrategy::TrimOldest {
+            if let Some(turn) = trim_turn(turn, remaining) {
+                let cost = turn_tokens(&turn);
+                used_tokens += cost;
+                included.push(turn);
+                trimmed = true;
+            }
+        }
+        break;
+    }
+
+    let dropped_turns = history.len() - included.len() + usize::from(trimmed);
+
+    let mut messages = Vec::with_capacity(included.len() * 2 + 2);
+    messages.push(LlmMessage::system(system_prompt));
+    for turn in included.into_iter().rev() {
+        messages.push(LlmMessage::user(turn.user));
+        messages.push(LlmMessage::assistant(turn.assistant));
+    }
+    messages.push(LlmMessage::user(new_message));
+
+    ContextWindow {
+        messages,
+        used_tokens,
+        dropped_turns,
+        trimmed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::llm::LlmRole;
+
+    fn turn(user: &str, assistant: &str) -> Turn {
+        Turn {
+            user: user.to_string(),
+            assistant: assistant.to_string(),
+        }
+    }
+
+    fn history() -> Vec<Turn> {
+        (0..5)
+            .map(|i| turn(&format!("问题{}", i), &"回答".repeat(20)))
+            .collect()
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        assert_eq!(estimate_tokens("abcd"), 1);
+        assert_eq!(estimate_tokens("abcde"), 2);
+        assert_eq!(estimate_tokens("你好"), 2);
+        assert_eq!(estimate_tokens("你好ab"), 3);
+    }
+
+    #[test]
+    fn test_keeps_everything_within_budget() {
+        let window = build_context(
+            "prompt",
+            &history(),
+            "新问题",
+            10_000,
+            TruncateStrategy::DropOldest,
+        );
+        assert_eq!(window.dropped_turns, 0);
+        assert_eq!(window.messages.len(), 2 + 5 * 2);
+        assert_eq!(window.messages[0].role, LlmRole::System);
+        assert_eq!(window.messages[1].content, "问题0");
+        assert_eq!(window.messages.last().unwrap().content, "新问题");
+    }
+
+    #[test]
+    fn test_drops_oldest_turns_first() {
+        let history = history();
+        let fixed = message_tokens("prompt") + message_tokens("新问题");
+        let budget = fixed + turn_tokens(&history[0]) * 2 + 1;
+
+        let window = build_context(
+            "prompt",
+            &history,
+            "新问题",
+            budget,
+            TruncateStrategy::DropOldest,
+        );
+        assert_eq!(window.dropped_turns, 3);
+        assert!(window.used_tokens <= budget);
+        assert_eq!(window.messages[1].content, "问题3");
+        assert_eq!(window.messages[3].content, "问题4");
+    }
+
+    #[test]
+    fn test_trims_oldest_included_turn() {
+        let history = history();
+        let fixed = message_tokens("prompt") + message_tokens("新问题");
+        let budget = fixed + turn_tokens(&history[0]) + 45;
+
+        let window = build_context(
+            "prompt",
+            &history,
+            "新问题",
+            budget,
+            TruncateStrategy::TrimOldest,
+        );
+        assert!(window.trimmed);
+        assert!(window.used_tokens <= budget);
+        // 最新一轮完整保留，上一轮被裁剪
+        assert_eq!(window.messages.len(), 2 + 2 * 2);
+        assert_eq!(window.messages[1].content, "问题3");
+        assert!(window.messages[2].content.starts_with(TRIM_MARKER));
+        assert_eq!(window.messages[4].content, history[4].assistant);
+    }
+
+    #[test]
+    fn test_trim_to_tokens_keeps_tail_within_budget() {
+        let text = "abcd中文".repeat(20_000);
+        let trimmed = trim_to_tokens(&text, 100);
+        assert!(trimmed.starts_with(TRIM_MARKER));
+        assert!(text.ends_with(trimmed.trim_start_matches(TRIM_MARKER)));
+        assert!(estimate_tokens(&trimmed) <= 100);
+        // 多保留一个字就会超出预算
+        let kept = trimmed.chars().count() - 1;
+        let longer = text.chars().rev().take(kept + 1).collect::<String>();
+        assert!(estimate_tokens(&longer) + estimate_tokens(TRIM_MARKER) > 100);
+    }
+
+    #[test]
+    fn test_system_and_new_message_always_kept() {
+        let window = build_context(
+            "prompt",
+            &history(),
+            "新问题",
+            0,
+            TruncateStrategy::TrimOldest,
+        );
+        assert_eq!(window.messages.len(), 2);
+        assert_eq!(window.dropped_turns, 5);
+    }
+}
@@ -0,0 +1,163 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::Stream;
+
+use super::{LlmProvider, LlmRequest, LlmStream};
+use crate::services::context::estimate_tokens;
+use crate::services::metrics::{
+    LLM_ERRORS_TOTAL, LLM_REQUESTS_TOTAL, LLM_REQUEST_SECONDS, LLM_TOKENS_TOTAL,
+};
+
+const KIND_COMPLETE: &str = "complete";
+const KIND_STREAM: &str = "stream";
+
+/// 给任意 provider 加上请求数、错误数、耗时和 token 用量的统计
+pub struct InstrumentedProvider {
+    inner: Arc<dyn LlmProvider>,
+}
+
+impl InstrumentedProvider {
+    pub fn new(inner: Arc<dyn LlmProvider>) -> Self {
+        Self { inner }
+    }
+
+    fn start(&self, kind: &str, request: &LlmRequest) -> Instant {
+        let name = self.inner.name();
+        LLM_REQUESTS_TOTAL.with_label_values(&[name, kind]).inc();
+        let prompt_tokens = request
+            .messages
+            .iter()
+            .map(|m| estimate_tokens(&m.content))
+            .sum::<usize>();
+        LLM_TOKENS_TOTAL
+            .with_label_values(&[name, "prompt"])
+            .inc_by(prompt_tokens as u64);
+        Instant::now()
+    }
+}
+
+#[async_trait]
+impl LlmProvider for InstrumentedProvider {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn complete(&self, request: LlmRequest) -> Result<String> {
+        let name = self.inner.name();
+        let start = self.start(KIND_COMPLETE, &request);
+        let result = self.inner.complete(request).await;
+        match &result {
+            Ok(reply) => {
+                LLM_REQUEST_SECONDS
+                    .with_label_values(&[name, KIND_COMPLETE])
+                    .observe(start.elapsed().as_secs_f64());
+                LLM_TOKENS_TOTAL
+                    .with_label_values(&[name, "completion"])
+                    .inc_by(estimate_tokens(reply) as u64);
+            }
+            Err(_) => LLM_ERRORS_TOTAL
+                .with_label_values(&[name, KIND_COMPLETE])
+                .inc(),
+        }
+        result
+    }
+
+    async fn complete_stream(&self, request: LlmRequest) -> Result<LlmStream> {
+        let name = self.inner.name();
+        let start = self.start(KIND_STREAM, &request);
+        match self.inner.complete_stream(request).await {
+            Ok(stream) => Ok(Box::pin(InstrumentedStream {
+                inner: stream,
+                provider: name,
+                start,
+                reply: String::new(),
+            })),
+            Err(e) => {
+                LLM_ERRORS_TOTAL
+                    .with_label_values(&[name, KIND_STREAM])
+                    .inc();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// 流读完时记录耗时，被提前丢弃 (打断) 时也记录已经生成的 token
+struct InstrumentedStream {
+    inner: LlmStream,
+    provider: &'static str,
+    start: Instant,
+    reply: String,
+}
+
+impl Stream for InstrumentedStream {
+    type Item = Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.as_mut().poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(content))) => self.reply.push_str(content),
+            Poll::Ready(Some(Err(_))) => LLM_ERRORS_TOTAL
+                .with_label_values(&[self.provider, KIND_STREAM])
+                .inc(),
+            Poll::Ready(None) => LLM_REQUEST_SECONDS
+                .with_label_values(&[self.provider, KIND_STREAM])
+                .observe(self.start.elapsed().as_secs_f64()),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for InstrumentedStream {
+    fn drop(&mut self) {
+        LLM_TOKENS_TOTAL
+            .with_label_values(&[self.provider, "completion"])
+            .inc_by(estimate_tokens(&self.reply) as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use super::*;
+    use crate::services::llm::mock::MockProvider;
+    use crate::services::llm::LlmMessage;
+
+    #[tokio::test]
+    async fn test_stream_metrics() {
+        let provider = InstrumentedProvider::new(Arc::new(MockProvider::new(Some(
+            "你好，世界。".to_string(),
+        ))));
+        let tokens = |direction| {
+            LLM_TOKENS_TOTAL
+                .with_label_values(&["mock", direction])
+                .get()
+        };
+        let (prompt, completion) = (tokens("prompt"), tokens("completion"));
+
+        let request = LlmRequest {
+            messages: vec![LlmMessage::user("在吗")],
+            ..Default::default()
+        };
+        let mut stream = provider.complete_stream(request).await.unwrap();
+        // 只读一段就丢弃，模拟被打断
+        assert_eq!(stream.next().await.unwrap().unwrap(), "你好，世");
+        drop(stream);
+
+        assert!(
+            LLM_REQUESTS_TOTAL
+                .with_label_values(&["mock", KIND_STREAM])
+                .get()
+                >= 1
+        );
+        assert!(tokens("prompt") >= prompt + 2);
+        assert!(tokens("completion") >= completion + 4);
+    }
+}
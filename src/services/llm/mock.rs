@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+
+use super::{LlmProvider, LlmRequest, LlmRole, LlmStream};
+
+const MOCK_CHUNK_CHARS: usize = 4;
+
+/// 进程内的确定性实现，不访问网络，用于测试
+pub struct MockProvider {
+    reply: Option<String>,
+    /// 流式输出这么多段后返回错误，complete 直接返回错误
+    fail_after: Option<usize>,
+    /// 每段输出前等待的时间，用于测试打断
+    chunk_delay: Duration,
+}
+
+impl MockProvider {
+    pub fn new(reply: Option<String>) -> Self {
+        Self {
+            reply,
+            fail_after: None,
+            chunk_delay: Duration::ZERO,
+        }
+    }
+
+    pub fn with_failure(mut self, after_chunks: usize) -> Self {
+        self.fail_after = Some(after_chunks);
+        self
+    }
+
+    pub fn with_chunk_delay(mut self, delay: Duration) -> Self {
+        self.chunk_delay = delay;
+        self
+    }
+
+    fn reply_for(&self, request: &LlmRequest) -> String {
+        if let Some(reply) = &self.reply {
+            return reply.clone();
+        }
+
+        let last_user_message = request
+            .messages
+            .iter()
+            .rev()
+            .find(|m| m.role == LlmRole::User)
+            .map(|m| m.content.as_str())
+            .unwrap_or("");
+        format!("你说的是：{}。", last_user_message)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn complete(&self, request: LlmRequest) -> Result<String> {
+        if self.fail_after.is_some() {
+            return Err(anyhow::anyhow!("Mock llm failure"));
+        }
+        Ok(self.reply_for(&request))
+    }
+
+    async fn complete_stream(&self, request: LlmRequest) -> Result<LlmStream> {
+        let chars = self.reply_for(&request).chars().collect::<Vec<char>>();
+        let mut chunks = chars
+            .chunks(MOCK_CHUNK_CHARS)
+            .map(|chunk| Ok(chunk.iter().collect::<String>()))
+            .collect::<Vec<Result<String>>>();
+        if let Some(fail_after) = self.fail_after {
+            chunks.truncate(fail_after);
+            chunks.push(Err(anyhow::anyhow!(
+                "Mock llm failure after {} chunks",
+                fail_after
+            )));
+        }
+
+        let delay = self.chunk_delay;
+        let stream = futures_util::stream::iter(chunks).then(move |chunk| async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            chunk
+        });
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::services::llm::LlmMessage;
+
+    fn request(message: &str) -> LlmRequest {
+        LlmRequest {
+            messages: vec![LlmMessage::user(message)],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream_matches_complete() {
+        let provider = MockProvider::new(None);
+        let request = LlmRequest {
+            messages: vec![LlmMessage::system("prompt"), LlmMessage::user("你好")],
+            ..Default::default()
+        };
+
+        let full = provider.complete(request.clone()).await.unwrap();
+        assert_eq!(full, "你说的是：你好。");
+
+        let mut stream = provider.complete_stream(request).await.unwrap();
+        let mut streamed = String::new();
+        while let Some(chunk) = stream.next().await {
+            streamed.push_str(&chunk.unwrap());
+        }
+        assert_eq!(streamed, full);
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream_chunks() {
+        let provider = MockProvider::new(Some("你好，我在。好的".to_string()));
+        let stream = provider.complete_stream(request("在吗")).await.unwrap();
+        let chunks = stream
+            .map(|chunk| chunk.unwrap())
+            .collect::<Vec<String>>()
+            .await;
+        assert_eq!(chunks, vec!["你好，我", "在。好的"]);
+    }
+
+    #[tokio::test]
+    async fn test_mock_stream_cancellation() {
+        let provider = MockProvider::new(Some("你好，我在。好的".to_string()))
+            .with_chunk_delay(Duration::from_millis(20));
+        let mut stream = provider.complete_stream(request("在吗")).await.unwrap();
+
+        // 和 Chat 的生成循环一样，收到第一段后取消
+        let cancel = CancellationToken::new();
+        let mut chunks = Vec::new();
+        loop {
+            tokio::select! {
+                biased;
+                _ = cancel.cancelled() => break,
+                chunk = stream.next() => match chunk {
+                    Some(chunk) => {
+                        chunks.push(chunk.unwrap());
+                        cancel.cancel();
+                    }
+                    None => break,
+                },
+            }
+        }
+        assert_eq!(chunks, vec!["你好，我"]);
+        // 取消后剩下的内容仍然可以读出，说明上面是被取消而不是读完了
+        assert_eq!(stream.next().await.unwrap().unwrap(), "在。好的");
+    }
+
+    #[tokio::test]
+    async fn test_mock_failure() {
+        let provider = MockProvider::new(Some("你好，我在。好的".to_string())).with_failure(1);
+        assert!(provider.complete(request("在吗")).await.is_err());
+
+        let mut stream = provider.complete_stream(request("在吗")).await.unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), "你好，我");
+        assert!(stream.next().await.unwrap().is_err());
+        assert!(stream.next().await.is_none());
+
+        // 第一段之前就出错
+        let provider = MockProvider::new(None).with_failure(0);
+        let mut stream = provider.complete_stream(request("在吗")).await.unwrap();
+        assert!(stream.next().await.unwrap().is_err());
+    }
+}
@@ -0,0 +1,256 @@
+pub mod instrumented;
+pub mod mock;
+pub mod ollama;
+pub mod openai;
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use config::Config;
+use futures_util::Stream;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{GLOBAL_CONFIG, OZ_SERVER_CONFIG};
+use crate::constant::{
+    API_BASE_URL, DEFAULT_LLM_MODEL, DEFAULT_OLLAMA_BASE_URL, DEFAULT_OLLAMA_MODEL, LLM_CONFIG,
+    LLM_PROVIDER_MOCK, LLM_PROVIDER_OLLAMA, LLM_PROVIDER_OPENAI, MAX_ROLE_MAX_TOKENS,
+    OPEN_API_KEY,
+};
+
+const MAX_MODEL_NAME_LEN: usize = 64;
+const MAX_STOP_SEQUENCES: usize = 4;
+const MAX_STOP_SEQUENCE_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmRole {
+    System,
+    User,
+    Assistant,
+}
+
+impl LlmRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LlmRole::System => "system",
+            LlmRole::User => "user",
+            LlmRole::Assistant => "assistant",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LlmMessage {
+    pub role: LlmRole,
+    pub content: String,
+}
+
+impl LlmMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: LlmRole::System,
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: LlmRole::User,
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: LlmRole::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
+/// 生成参数，未设置的字段交给 provider 使用其默认值
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    /// 为空时使用 provider 配置的默认模型
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Option<Vec<String>>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+}
+
+impl GenerationParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(model) = &self.model {
+            if model.trim().is_empty() || model.len() > MAX_MODEL_NAME_LEN {
+                return Err("Invalid model".to_string());
+            }
+        }
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                return Err("temperature must be between 0 and 2".to_string());
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(top_p > 0.0 && top_p <= 1.0) {
+                return Err("top_p must be in (0, 1]".to_string());
+            }
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            if max_tokens == 0 || max_tokens > MAX_ROLE_MAX_TOKENS {
+                return Err(format!(
+                    "max_tokens must be between 1 and {}",
+                    MAX_ROLE_MAX_TOKENS
+                ));
+            }
+        }
+        if let Some(stop) = &self.stop {
+            if stop.len() > MAX_STOP_SEQUENCES {
+                return Err(format!(
+                    "At most {} stop sequences are allowed",
+                    MAX_STOP_SEQUENCES
+                ));
+            }
+            if stop.iter().any(|s| s.is_empty() || s.len() > MAX_STOP_SEQUENCE_LEN) {
+                return Err("Invalid stop sequence".to_string());
+            }
+        }
+        for (name, penalty) in [
+            ("presence_penalty", self.presence_penalty),
+            ("frequency_penalty", self.frequency_penalty),
+        ] {
+            if let Some(penalty) = penalty {
+                if !(-2.0..=2.0).contains(&penalty) {
+                    return Err(format!("{} must be between -2 and 2", name));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LlmRequest {
+    pub messages: Vec<LlmMessage>,
+    pub params: GenerationParams,
+}
+
+/// 流式输出，每一项是一段增量文本
+pub type LlmStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn complete(&self, request: LlmRequest) -> Result<String>;
+
+    async fn complete_stream(&self, request: LlmRequest) -> Result<LlmStream>;
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LlmConfig {
+    pub provider: Option<String>,
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+    /// 仅 mock provider 使用，为空时复述用户的最后一句话
+    pub mock_reply: Option<String>,
+    /// 仅 mock provider 使用，流式输出每段之间的间隔，用于测试打断
+    pub mock_chunk_delay_ms: Option<u64>,
+}
+
+pub fn build_provider(config: &Config) -> Result<Arc<dyn LlmProvider>> {
+    let llm_config = config.get::<LlmConfig>(LLM_CONFIG).unwrap_or_default();
+    let provider = llm_config
+        .provider
+        .clone()
+        .unwrap_or(LLM_PROVIDER_OPENAI.to_string());
+
+    match provider.as_str() {
+        LLM_PROVIDER_OPENAI => {
+            // 兼容旧配置：顶层的 open_api_key，最后是全局配置的 openai_api_key
+            let api_key = llm_config
+                .api_key
+                .or_else(|| config.get::<String>(OPEN_API_KEY).ok())
+                .or_else(|| GLOBAL_CONFIG.openai_api_key.clone())
+                .ok_or_else(|| anyhow::anyhow!("openai_api_key is not configured"))?;
+            Ok(Arc::new(openai::OpenAiProvider::new(
+                llm_config.api_base.as_deref().unwrap_or(API_BASE_URL),
+                &api_key,
+                llm_config.model.as_deref().unwrap_or(DEFAULT_LLM_MODEL),
+            )))
+        }
+        LLM_PROVIDER_OLLAMA => Ok(Arc::new(ollama::OllamaProvider::new(
+            llm_config
+                .api_base
+                .as_deref()
+                .unwrap_or(DEFAULT_OLLAMA_BASE_URL),
+            // 本地 Ollama 没有 DeepSeek 的模型
+            llm_config.model.as_deref().unwrap_or(DEFAULT_OLLAMA_MODEL),
+        ))),
+        LLM_PROVIDER_MOCK => Ok(Arc::new(
+            mock::MockProvider::new(llm_config.mock_reply).with_chunk_delay(Duration::from_millis(
+                llm_config.mock_chunk_delay_ms.unwrap_or_default(),
+            )),
+        )),
+        other => Err(anyhow::anyhow!("Unknown llm provider: {}", other)),
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref LLM_PROVIDER: Arc<dyn LlmProvider> = Arc::new(
+        instrumented::InstrumentedProvider::new(
+            build_provider(&OZ_SERVER_CONFIG).expect("Failed to build llm provider"),
+        ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_generation_params() {
+        assert!(GenerationParams::default().validate().is_ok());
+
+        let params = GenerationParams {
+            temperature: Some(0.7),
+            top_p: Some(1.0),
+            max_tokens: Some(1024),
+            stop: Some(vec!["。".to_string()]),
+            presence_penalty: Some(-1.0),
+            ..Default::default()
+        };
+        assert!(params.validate().is_ok());
+
+        let invalid = [
+            GenerationParams {
+                temperature: Some(2.5),
+                ..Default::default()
+            },
+            GenerationParams {
+                top_p: Some(0.0),
+                ..Default::default()
+            },
+            GenerationParams {
+                max_tokens: Some(0),
+                ..Default::default()
+            },
+            GenerationParams {
+                stop: Some(vec!["a".to_string(); 5]),
+                ..Default::default()
+            },
+            GenerationParams {
+                frequency_penalty: Some(f32::NAN),
+                ..Default::default()
+            },
+        ];
+        for params in invalid {
+            assert!(params.validate().is_err(), "{:?}", params);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+
+use super::{LlmProvider, LlmRequest, LlmStream};
+
+/// Ollama 风格的本地推理服务 (POST /api/chat，流式输出为 NDJSON)
+pub struct OllamaProvider {
+    client: Client,
+    api_base: String,
+    model: String,
+}
+
+#[derive(Serialize)]
+struct OllamaMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+}
+
+#[derive(Serialize)]
+struct OllamaChatRequest<'a> {
+    model: String,
+    messages: Vec<OllamaMessage<'a>>,
+    stream: bool,
+    options: OllamaOptions,
+}
+
+#[derive(Deserialize)]
+struct OllamaResponseMessage {
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Deserialize)]
+struct OllamaChatChunk {
+    message: Option<OllamaResponseMessage>,
+    #[serde(default)]
+    done: bool,
+    error: Option<String>,
+}
+
+impl OllamaChatChunk {
+    fn into_content(self) -> Result<String> {
+        if let Some(error) = self.error {
+            return Err(anyhow::anyhow!("ollama error: {}", error));
+        }
+        Ok(self.message.map(|m| m.content).unwrap_or_default())
+    }
+}
+
+struct NdjsonState<S> {
+    body: S,
+    buffer: Vec<u8>,
+    eof: bool,
+    finished: bool,
+}
+
+impl OllamaProvider {
+    pub fn new(api_base: &str, model: &str) -> Self {
+        Self {
+            client: Client::new(),
+            api_base: api_base.trim_end_matches('/').to_string(),
+            model: model.to_string(),
+        }
+    }
+
+    async fn send(&self, request: &LlmRequest, stream: bool) -> Result<reqwest::Response> {
+        let params = &request.params;
+        let body = OllamaChatRequest {
+            model: params.model.clone().unwrap_or(self.model.clone()),
+            messages: request
+                .messages
+                .iter()
+                .map(|m| OllamaMessage {
+                    role: m.role.as_str(),
+                    content: &m.content,
+                })
+                .collect(),
+            stream,
+            options: OllamaOptions {
+                num_predict: params.max_tokens,
+                temperature: params.temperature,
+                top_p: params.top_p,
+                stop: params.stop.clone(),
+                presence_penalty: params.presence_penalty,
+                frequency_penalty: params.frequency_penalty,
+            },
+        };
+
+        let response = self
+            .client
+            .post(format!("{}/api/chat", self.api_base))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    async fn complete(&self, request: LlmRequest) -> Result<String> {
+        let response = self.send(&request, false).await?;
+        let chunk = response.json::<OllamaChatChunk>().await?;
+        chunk.into_content()
+    }
+
+    async fn complete_stream(&self, request: LlmRequest) -> Result<LlmStream> {
+        let response = self.send(&request, true).await?;
+
+        let state = NdjsonState {
+            body: Box::pin(response.bytes_stream()),
+            buffer: Vec::new(),
+            eof: false,
+            finished: false,
+        };
+
+        let stream = futures_util::stream::unfold(state, |mut state| async move {
+            loop {
+                if state.finished {
+                    return None;
+                }
+
+                if let Some(pos) = state.buffer.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = state.buffer.drain(..=pos).collect();
+                    if line.iter().all(|b| b.is_ascii_whitespace()) {
+                        continue;
+                    }
+                    let item = serde_json::from_slice::<OllamaChatChunk>(&line)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|chunk| {
+                            if chunk.done {
+                                state.finished = true;
+                            }
+                            chunk.into_content()
+                        });
+                    if item.is_err() {
+                        state.finished = true;
+                    }
+                    return Some((item, state));
+                }
+
+                if state.eof {
+                    return None;
+                }
+
+                match state.body.next().await {
+                    Some(Ok(bytes)) => state.buffer.extend_from_slice(&bytes),
+                    Some(Err(e)) => {
+                        state.finished = true;
+                        return Some((Err(e.into()), state));
+                    }
+                    None => {
+                        // 最后一行可能没有换行符
+                        state.eof = true;
+                        state.buffer.push(b'\n');
+                    }
+                }
+            }
+        });
+
+        Ok(Box::pin(stream))
+    }
+}
@@ -0,0 +1,115 @@
+use anyhow::Result;
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+    CreateChatCompletionRequest, CreateChatCompletionRequestArgs, Stop,
+};
+use async_openai::{config::OpenAIConfig, Client};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+
+use super::{LlmMessage, LlmProvider, LlmRequest, LlmRole, LlmStream};
+
+/// OpenAI 协议兼容的服务，例如 DeepSeek
+pub struct OpenAiProvider {
+    client: Client<OpenAIConfig>,
+    model: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(api_base: &str, api_key: &str, model: &str) -> Self {
+        let config = OpenAIConfig::new()
+            .with_api_base(api_base)
+            .with_api_key(api_key);
+
+        Self {
+            client: Client::with_config(config),
+            model: model.to_string(),
+        }
+    }
+
+    fn build_request(&self, request: LlmRequest) -> Result<CreateChatCompletionRequest> {
+        let messages = request
+            .messages
+            .iter()
+            .map(to_openai_message)
+            .collect::<Result<Vec<_>>>()?;
+
+        let params = request.params;
+        let mut args = CreateChatCompletionRequestArgs::default();
+        args.model(params.model.unwrap_or(self.model.clone()))
+            .messages(messages);
+        if let Some(max_tokens) = params.max_tokens {
+            args.max_tokens(max_tokens);
+        }
+        if let Some(temperature) = params.temperature {
+            args.temperature(temperature);
+        }
+        if let Some(top_p) = params.top_p {
+            args.top_p(top_p);
+        }
+        if let Some(stop) = params.stop {
+            args.stop(Stop::StringArray(stop));
+        }
+        if let Some(presence_penalty) = params.presence_penalty {
+            args.presence_penalty(presence_penalty);
+        }
+        if let Some(frequency_penalty) = params.frequency_penalty {
+            args.frequency_penalty(frequency_penalty);
+        }
+
+        Ok(args.build()?)
+    }
+}
+
+fn to_openai_message(message: &LlmMessage) -> Result<ChatCompletionRequestMessage> {
+    let message = match message.role {
+        LlmRole::System => ChatCompletionRequestSystemMessageArgs::default()
+            .content(message.content.clone())
+            .build()?
+            .into(),
+        LlmRole::User => ChatCompletionRequestUserMessageArgs::default()
+            .content(message.content.clone())
+            .build()?
+            .into(),
+        LlmRole::Assistant => ChatCompletionRequestAssistantMessageArgs::default()
+            .content(message.content.clone())
+            .build()?
+            .into(),
+    };
+    Ok(message)
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn name(&self) -> &'static str {
+        "openai"
+    }
+
+    async fn complete(&self, request: LlmRequest) -> Result<String> {
+        let request = self.build_request(request)?;
+        let response = self.client.chat().create(request).await?;
+
+        let content = response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .unwrap_or_default();
+        Ok(content)
+    }
+
+    async fn complete_stream(&self, request: LlmRequest) -> Result<LlmStream> {
+        let request = self.build_request(request)?;
+        let stream = self.client.chat().create_stream(request).await?;
+
+        let stream = stream.map(|result| match result {
+            Ok(response) => Ok(response
+                .choices
+                .iter()
+                .filter_map(|choice| choice.delta.content.clone())
+                .collect::<String>()),
+            Err(err) => Err(anyhow::anyhow!("error: {err}")),
+        });
+        Ok(Box::pin(stream))
+    }
+}
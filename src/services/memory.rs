@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use log::{debug, error};
+
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::{
+    MAX_MEMORIES_PER_ROLE, MAX_MEMORY_LEN, MEMORY_EXTRACTION, MEMORY_PROMPT_LIMIT,
+    PROMPT_EXTRACT_MEMORY, PROMPT_MEMORY_PREFIX,
+};
+use crate::models::schema::{sessions, user_memories};
+use crate::models::user_memory::UserMemory;
+use crate::services::llm::{GenerationParams, LlmMessage, LlmProvider, LlmRequest};
+use crate::utils;
+
+const EXTRACT_MAX_TOKENS: u32 = 256;
+
+pub fn extraction_enabled() -> bool {
+    OZ_SERVER_CONFIG
+        .get::<bool>(MEMORY_EXTRACTION)
+        .unwrap_or(false)
+}
+
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_ascii_punctuation())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+fn bigrams(text: &str) -> HashSet<(char, char)> {
+    let chars = normalize(text).chars().collect::<Vec<char>>();
+    chars.windows(2).map(|w| (w[0], w[1])).collect()
+}
+
+/// 记忆和消息共有的相邻字符对数量，中英文都适用的粗略相关度
+pub fn relevance(memory: &str, message: &str) -> usize {
+    let message = bigrams(message);
+    bigrams(memory).intersection(&message).count()
+}
+
+/// 从模型输出中解析 JSON 字符串数组，容忍前后多余的文字
+pub fn parse_facts(output: &str) -> Vec<String> {
+    let (start, end) = match (output.find('['), output.rfind(']')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Vec::new(),
+    };
+
+    serde_json::from_str::<Vec<String>>(&output[start..=end])
+        .unwrap_or_default()
+        .into_iter()
+        .map(|fact| fact.trim().to_string())
+        .filter(|fact| !fact.is_empty() && fact.chars().count() <= MAX_MEMORY_LEN)
+        .collect()
+}
+
+/// 先按相关度，再按更新时间选出最多 limit 条
+pub fn select_relevant(
+    mut memories: Vec<UserMemory>,
+    message: &str,
+    limit: usize,
+) -> Vec<UserMemory> {
+    memories.sort_by(|a, b| {
+        relevance(&b.content, message)
+            .cmp(&relevance(&a.content, message))
+            .then(b.updated_at.cmp(&a.updated_at))
+    });
+    memories.truncate(limit);
+    memories
+}
+
+pub fn load_memories(
+    conn: &mut PgConnection,
+    user_id: &str,
+    role_id: &str,
+) -> QueryResult<Vec<UserMemory>> {
+    user_memories::table
+        .filter(user_memories::user_id.eq(user_id))
+        .filter(user_memories::role_id.eq(role_id))
+        .order(user_memories::updated_at.desc())
+        .limit(MAX_MEMORIES_PER_ROLE)
+        .select(UserMemory::as_select())
+        .load(conn)
+}
+
+pub fn load_relevant(
+    conn: &mut PgConnection,
+    user_id: &str,
+    role_id: &str,
+    message: &str,
+) -> QueryResult<Vec<UserMemory>> {
+    let memories = load_memories(conn, user_id, role_id)?;
+    Ok(select_relevant(memories, message, MEMORY_PROMPT_LIMIT))
+}
+
+/// 会话保存的记忆，没有时为空
+pub fn load_session_memories(conn: &mut PgConnection, session_id: &str) -> Result<Vec<String>> {
+    let memories = sessions::table
+        .filter(sessions::session_id.eq(session_id))
+        .select(sessions::memories)
+        .first::<Option<String>>(conn)
+        .optional()?
+        .flatten();
+    Ok(match memories {
+        Some(memories) => serde_json::from_str(&memories)?,
+        None => Vec::new(),
+    })
+}
+
+pub fn to_session_memories(memories: &[String]) -> Option<String> {
+    if memories.is_empty() {
+        return None;
+    }
+    serde_json::to_string(memories).ok()
+}
+
+/// 把记忆拼进 system prompt
+pub fn with_memories(prompt: &str, memories: &[String]) -> String {
+    if memories.is_empty() {
+        return prompt.to_string();
+    }
+
+    let facts = memories
+        .iter()
+        .map(|m| format!("- {}", m))
+        .collect::<Vec<String>>()
+        .join("\n");
+    format!("{}\n\n{}\n{}", prompt, PROMPT_MEMORY_PREFIX, facts)
+}
+
+/// 后台从一轮对话中提取用户事实并保存
+pub fn spawn_extract(
+    db_pool: Pool<ConnectionManager<PgConnection>>,
+    llm: Arc<dyn LlmProvider>,
+    user_id: String,
+    role_id: String,
+    user_message: String,
+    assistant_message: String,
+    model: Option<String>,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = extract_memories(
+            &db_pool,
+            llm,
+            &user_id,
+            &role_id,
+            &user_message,
+            &assistant_message,
+            model,
+        )
+        .await
+        {
+            error!("Failed to extract memories for user {}: {}", user_id, e);
+        }
+    });
+}
+
+async fn extract_memories(
+    db_pool: &Pool<ConnectionManager<PgConnection>>,
+    llm: Arc<dyn LlmProvider>,
+    user_id: &str,
+    role_id: &str,
+    user_message: &str,
+    assistant_message: &str,
+    model: Option<String>,
+) -> Result<()> {
+    let existing = load_memories(&mut *db_pool.get()?, user_id, role_id)?;
+    if existing.len() as i64 >= MAX_MEMORIES_PER_ROLE {
+        debug!(
+            "user {} has too many memories for role {}",
+            user_id, role_id
+        );
+        return Ok(());
+    }
+
+    let known = existing
+        .iter()
+        .map(|m| m.content.as_str())
+        .collect::<Vec<&str>>()
+        .join("；");
+    let request = LlmRequest {
+        messages: vec![
+            LlmMessage::system(PROMPT_EXTRACT_MEMORY),
+            LlmMessage::user(format!(
+                "已知信息：{}\n\n用户：{}\n助手：{}",
+                known, user_message, assistant_message
+            )),
+        ],
+        params: GenerationParams {
+            model,
+            max_tokens: Some(EXTRACT_MAX_TOKENS),
+            ..Default::default()
+        },
+    };
+    let output = llm.complete(request).await?;
+
+    let mut seen = existing
+        .iter()
+        .map(|m| normalize(&m.content))
+        .collect::<HashSet<String>>();
+    let now = SystemTime::now();
+    let new_memories = parse_facts(&output)
+        .into_iter()
+        .filter(|fact| seen.insert(normalize(fact)))
+        .take((MAX_MEMORIES_PER_ROLE as usize).saturating_sub(existing.len()))
+        .map(|content| UserMemory {
+            id: utils::gen_new_id(),
+            user_id: user_id.to_string(),
+            role_id: role_id.to_string(),
+            content,
+            created_at: now,
+            updated_at: now,
+        })
+        .collect::<Vec<UserMemory>>();
+
+    if new_memories.is_empty() {
+        return Ok(());
+    }
+
+    // 并发的两轮可能提取出同一条，靠 (user_id, role_id, content) 唯一索引去重
+    diesel::insert_into(user_memories::table)
+        .values(&new_memories)
+        .on_conflict_do_nothing()
+        .execute(&mut db_pool.get()?)?;
+    debug!(
+        "saved {} memories for user {} role {}",
+        new_memories.len(),
+        user_id,
+        role_id
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory(content: &str, age_secs: u64) -> UserMemory {
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000 - age_secs);
+        UserMemory {
+            id: content.to_string(),
+            user_id: "u".to_string(),
+            role_id: "r".to_string(),
+            content: content.to_string(),
+            created_at: time,
+            updated_at: time,
+        }
+    }
+
+    #[test]
+    fn test_parse_facts() {
+        assert_eq!(
+            parse_facts("好的：[\"用户叫小明\", \" 养了一只猫 \", \"\"]"),
+            vec!["用户叫小明".to_string(), "养了一只猫".to_string()]
+        );
+        assert!(parse_facts("[]").is_empty());
+        assert!(parse_facts("没有").is_empty());
+        assert!(parse_facts("[not json]").is_empty());
+    }
+
+    #[test]
+    fn test_select_relevant() {
+        let memories = vec![
+            memory("用户叫小明", 10),
+            memory("用户养了一只猫叫咪咪", 100),
+            memory("用户喜欢炉石传说", 1),
+        ];
+
+        let selected = select_relevant(memories, "咪咪今天不吃饭", 2);
+        assert_eq!(selected[0].content, "用户养了一只猫叫咪咪");
+        // 其余按最近更新排序
+        assert_eq!(selected[1].content, "用户喜欢炉石传说");
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, HistogramVec,
+    IntCounterVec, IntGauge, TextEncoder,
+};
+
+use crate::json::ws::TurnMetrics;
+use crate::models::schema::sessions;
+
+// 外部调用的耗时，从几十毫秒到几十秒
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0];
+
+lazy_static::lazy_static! {
+    /// 语音对话各阶段的耗时，stage 对应 TurnMetrics 的字段
+    pub static ref TURN_STAGE_SECONDS: HistogramVec = register_histogram_vec!(
+        "oz_voice_turn_stage_seconds",
+        "Latency of each stage of a voice turn",
+        &["stage"],
+        LATENCY_BUCKETS.to_vec()
+    )
+    .expect("Failed to register turn stage histogram");
+
+    /// route 是路由的模板，例如 /api/devices/{device_id}
+    pub static ref HTTP_REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "oz_http_requests_total",
+        "HTTP requests by route and status",
+        &["method", "route", "status"]
+    )
+    .expect("Failed to register http request counter");
+
+    pub static ref HTTP_REQUEST_SECONDS: HistogramVec = register_histogram_vec!(
+        "oz_http_request_duration_seconds",
+        "HTTP request latency by route",
+        &["method", "route"]
+    )
+    .expect("Failed to register http latency histogram");
+
+    pub static ref WS_CONNECTIONS: IntGauge = register_int_gauge!(
+        "oz_ws_connections",
+        "Active voice WebSocket connections"
+    )
+    .expect("Failed to register websocket gauge");
+
+    /// kind 是 complete 或 stream
+    pub static ref LLM_REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "oz_llm_requests_total",
+        "LLM requests",
+        &["provider", "kind"]
+    )
+    .expect("Failed to register llm request counter");
+
+    pub static ref LLM_ERRORS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "oz_llm_errors_total",
+        "Failed LLM requests, including errors in the middle of a stream",
+        &["provider", "kind"]
+    )
+    .expect("Failed to register llm error counter");
+
+    pub static ref LLM_REQUEST_SECONDS: HistogramVec = register_histogram_vec!(
+        "oz_llm_request_duration_seconds",
+        "LLM request latency until the whole reply is received",
+        &["provider", "kind"],
+        LATENCY_BUCKETS.to_vec()
+    )
+    .expect("Failed to register llm latency histogram");
+
+    /// provider 不返回用量，按文本估算
+    pub static ref LLM_TOKENS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "oz_llm_tokens_total",
+        "Estimated LLM token usage",
+        &["provider", "direction"]
+    )
+    .expect("Failed to register llm token counter");
+
+    /// op 是 asr_start、asr_finish、tts (建立合成流) 或 tts_sentence (一句话合成完)。
+    /// asr_send 每段音频都会调用，不记耗时，只在 VOICE_ERRORS_TOTAL 里计错误
+    pub static ref VOICE_CALL_SECONDS: HistogramVec = register_histogram_vec!(
+        "oz_voice_call_duration_seconds",
+        "ASR and TTS call latency",
+        &["backend", "op"],
+        LATENCY_BUCKETS.to_vec()
+    )
+    .expect("Failed to register voice latency histogram");
+
+    /// op 和 VOICE_CALL_SECONDS 相同，另外还有 asr_send
+    pub static ref VOICE_ERRORS_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "oz_voice_errors_total",
+        "Failed ASR and TTS calls",
+        &["backend", "op"]
+    )
+    .expect("Failed to register voice error counter");
+
+    static ref DB_POOL_CONNECTIONS: IntGauge = register_int_gauge!(
+        "oz_db_pool_connections",
+        "Open database connections"
+    )
+    .expect("Failed to register db pool gauge");
+
+    static ref DB_POOL_IDLE_CONNECTIONS: IntGauge = register_int_gauge!(
+        "oz_db_pool_idle_connections",
+        "Idle database connections"
+    )
+    .expect("Failed to register db pool gauge");
+
+    static ref DB_POOL_MAX_CONNECTIONS: IntGauge = register_int_gauge!(
+        "oz_db_pool_max_connections",
+        "Maximum database connections"
+    )
+    .expect("Failed to register db pool gauge");
+
+    /// result 是 ok 或 error
+    pub static ref MQTT_PUBLISH_TOTAL: IntCounterVec = register_int_counter_vec!(
+        "oz_mqtt_publish_total",
+        "MQTT publish outcomes",
+        &["topic", "result"]
+    )
+    .expect("Failed to register mqtt counter");
+}
+
+fn outcome<T, E>(result: &Result<T, E>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(_) => "error",
+    }
+}
+
+/// topic 是 event 或 chat，不带设备和用户 id
+pub fn record_mqtt_publish<T, E>(topic: &str, result: &Result<T, E>) {
+    MQTT_PUBLISH_TOTAL
+        .with_label_values(&[topic, outcome(result)])
+        .inc();
+}
+
+/// 导出 Prometheus 文本格式，连接池的状态在这时读取
+pub fn render(db_pool: &Pool<ConnectionManager<PgConnection>>) -> Result<String> {
+    let state = db_pool.state();
+    DB_POOL_CONNECTIONS.set(state.connections as i64);
+    DB_POOL_IDLE_CONNECTIONS.set(state.idle_connections as i64);
+    DB_POOL_MAX_CONNECTIONS.set(db_pool.max_size() as i64);
+
+    Ok(TextEncoder::new().encode_to_string(&prometheus::gather())?)
+}
+
+/// 一轮语音对话中的时间点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TurnStage {
+    SpeechEnded,
+    AsrFinal,
+    LlmFirstToken,
+    FirstSentence,
+    TtsFirstByte,
+    LastAudioSent,
+}
+
+/// 记录一轮对话各个时间点，识别、生成和推送在不同的任务里，共享同一份
+#[derive(Debug, Default)]
+pub struct TurnTimings {
+    marks: Mutex<HashMap<TurnStage, Instant>>,
+}
+
+impl TurnTimings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 只记第一次，例如第一个 token、第一段音频
+    pub fn mark(&self, stage: TurnStage) {
+        self.mark_at(stage, Instant::now());
+    }
+
+    fn mark_at(&self, stage: TurnStage, at: Instant) {
+        self.marks.lock().unwrap().entry(stage).or_insert(at);
+    }
+
+    pub fn is_marked(&self, stage: TurnStage) -> bool {
+        self.marks.lock().unwrap().contains_key(&stage)
+    }
+
+    fn between(&self, from: TurnStage, to: TurnStage) -> Option<u64> {
+        let marks = self.marks.lock().unwrap();
+        let from = marks.get(&from)?;
+        let to = marks.get(&to)?;
+        Some(to.saturating_duration_since(*from).as_millis() as u64)
+    }
+
+    pub fn metrics(&self) -> TurnMetrics {
+        use TurnStage::*;
+        TurnMetrics {
+            asr_ms: self.between(SpeechEnded, AsrFinal),
+            llm_first_token_ms: self.between(AsrFinal, LlmFirstToken),
+            first_sentence_ms: self.between(AsrFinal, FirstSentence),
+            tts_first_byte_ms: self.between(FirstSentence, TtsFirstByte),
+            first_audio_ms: self.between(SpeechEnded, TtsFirstByte),
+            total_ms: self.between(SpeechEnded, LastAudioSent),
+        }
+    }
+}
+
+/// 把一轮的耗时计入直方图
+pub fn observe_turn(metrics: &TurnMetrics) {
+    let stages = [
+        ("asr", metrics.asr_ms),
+        ("llm_first_token", metrics.llm_first_token_ms),
+        ("first_sentence", metrics.first_sentence_ms),
+        ("tts_first_byte", metrics.tts_first_byte_ms),
+        ("first_audio", metrics.first_audio_ms),
+        ("total", metrics.total_ms),
+    ];
+    for (stage, ms) in stages {
+        if let Some(ms) = ms {
+            TURN_STAGE_SECONDS
+                .with_label_values(&[stage])
+                .observe(ms as f64 / 1000.0);
+        }
+    }
+}
+
+/// 记在会话上，排查某个会话为什么慢时用
+pub fn save_turn_metrics(
+    conn: &mut PgConnection,
+    session_id: &str,
+    metrics: &TurnMetrics,
+) -> Result<()> {
+    diesel::update(sessions::table.filter(sessions::session_id.eq(session_id)))
+        .set(sessions::last_turn_metrics.eq(serde_json::to_string(metrics)?))
+        .execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_turn_metrics() {
+        let timings = TurnTimings::new();
+        let start = Instant::now();
+        timings.mark_at(TurnStage::SpeechEnded, start);
+        timings.mark_at(TurnStage::AsrFinal, start + Duration::from_millis(300));
+        timings.mark_at(TurnStage::LlmFirstToken, start + Duration::from_millis(800));
+        timings.mark_at(
+            TurnStage::FirstSentence,
+            start + Duration::from_millis(1000),
+        );
+        timings.mark_at(TurnStage::TtsFirstByte, start + Duration::from_millis(1200));
+        // 只记第一次
+        timings.mark_at(TurnStage::TtsFirstByte, start + Duration::from_millis(2000));
+
+        let metrics = timings.metrics();
+        assert_eq!(metrics.asr_ms, Some(300));
+        assert_eq!(metrics.llm_first_token_ms, Some(500));
+        assert_eq!(metrics.first_sentence_ms, Some(700));
+        assert_eq!(metrics.tts_first_byte_ms, Some(200));
+        assert_eq!(metrics.first_audio_ms, Some(1200));
+        // 还没推送完
+        assert_eq!(metrics.total_ms, None);
+        assert!(!timings.is_marked(TurnStage::LastAudioSent));
+
+        observe_turn(&metrics);
+        assert_eq!(
+            TURN_STAGE_SECONDS
+                .with_label_values(&["asr"])
+                .get_sample_count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_record_mqtt_publish() {
+        record_mqtt_publish("chat", &Ok::<(), ()>(()));
+        record_mqtt_publish("chat", &Err::<(), ()>(()));
+        record_mqtt_publish("chat", &Err::<(), ()>(()));
+        let count = |result| {
+            MQTT_PUBLISH_TOTAL
+                .with_label_values(&["chat", result])
+                .get()
+        };
+        assert_eq!(count("ok"), 1);
+        assert_eq!(count("error"), 2);
+
+        let text = TextEncoder::new()
+            .encode_to_string(&prometheus::gather())
+            .unwrap();
+        assert!(text.contains("oz_mqtt_publish_total{result=\"error\",topic=\"chat\"} 2"));
+    }
+}
@@ -0,0 +1,9 @@
+pub mod audio;
+pub mod context;
+pub mod llm;
+pub mod memory;
+pub mod metrics;
+pub mod recording;
+pub mod summary;
+pub mod vad;
+pub mod voice;
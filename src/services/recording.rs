@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use config::Config;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::{
+    DEFAULT_RECORDING_DIR, RECORDING_CONFIG, RECORDING_PURGE_BATCH_SIZE,
+    RECORDING_RETENTION_CHECK_SECS, RECORDING_STORAGE_LOCAL,
+};
+use crate::models::schema::sections;
+use crate::services::audio::{wav_from_pcm, PcmFormat};
+
+/// 录音存储，key 是服务端生成的相对路径
+#[async_trait]
+pub trait RecordingStorage: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()>;
+
+    /// 不存在时返回 None
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// 存在本地目录下
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// key 只能是普通的相对路径，不能跳出根目录
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(anyhow::anyhow!("Invalid recording key: {}", key));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl RecordingStorage for LocalStorage {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RecordingConfig {
+    /// 默认不录音
+    #[serde(default)]
+    pub enabled: bool,
+    /// 存储后端，目前只有 local
+    pub storage: Option<String>,
+    /// local 存储的根目录
+    pub local_dir: Option<String>,
+    /// 录音保留天数，为空时一直保留
+    pub retention_days: Option<u64>,
+}
+
+impl RecordingConfig {
+    pub fn load() -> Self {
+        OZ_SERVER_CONFIG
+            .get::<RecordingConfig>(RECORDING_CONFIG)
+            .unwrap_or_default()
+    }
+}
+
+pub fn build_storage(config: &Config) -> Result<Option<Arc<dyn RecordingStorage>>> {
+    let recording_config = config
+        .get::<RecordingConfig>(RECORDING_CONFIG)
+        .unwrap_or_default();
+    if !recording_config.enabled {
+        return Ok(None);
+    }
+
+    let storage = recording_config
+        .storage
+        .unwrap_or(RECORDING_STORAGE_LOCAL.to_string());
+    match storage.as_str() {
+        RECORDING_STORAGE_LOCAL => Ok(Some(Arc::new(LocalStorage::new(
+            recording_config
+                .local_dir
+                .unwrap_or(DEFAULT_RECORDING_DIR.to_string()),
+        )))),
+        other => Err(anyhow::anyhow!("Unknown recording storage: {}", other)),
+    }
+}
+
+lazy_static::lazy_static! {
+    /// 未开启录音时为 None
+    pub static ref RECORDING_STORAGE: Option<Arc<dyn RecordingStorage>> =
+        build_storage(&OZ_SERVER_CONFIG).expect("Failed to build recording storage");
+}
+
+// 用户音频 PCM 和采样率
+type InputAudio = (Vec<u8>, u32);
+
+/// 一轮语音对话的录音，section 写入数据库前就确定了 key
+#[derive(Clone)]
+pub struct TurnRecording {
+    pub section_id: String,
+    pub input_key: String,
+    pub output_key: String,
+    /// 用户说的话和采样率，section 写入后才保存
+    input: Arc<Mutex<Option<InputAudio>>>,
+    /// 按句子序号保存合成的 PCM，句子是并发合成的
+    output: Arc<Mutex<BTreeMap<usize, Vec<u8>>>>,
+}
+
+impl TurnRecording {
+    pub fn new(user_id: &str, section_id: String) -> Self {
+        Self {
+            input_key: format!("{}/{}/input.wav", user_id, section_id),
+            output_key: format!("{}/{}/output.wav", user_id, section_id),
+            section_id,
+            input: Arc::new(Mutex::new(None)),
+            output: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    pub fn append_output(&self, index: usize, pcm: &[u8]) {
+        self.output
+            .lock()
+            .unwrap()
+            .entry(index)
+            .or_default()
+            .extend_from_slice(pcm);
+    }
+
+    fn output_pcm(&self) -> Vec<u8> {
+        self.output
+            .lock()
+            .unwrap()
+            .values()
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// 暂存用户说的话 (16 位单声道 PCM)
+    pub fn set_input(&self, pcm: Vec<u8>, sample_rate: u32) {
+        *self.input.lock().unwrap() = Some((pcm, sample_rate));
+    }
+
+    /// section 写入数据库后再保存，对话失败时不会留下没有引用的文件
+    pub fn save_input(&self) {
+        if let Some((pcm, sample_rate)) = self.input.lock().unwrap().take() {
+            spawn_save(self.input_key.clone(), pcm, sample_rate);
+        }
+    }
+
+    /// 保存合成的回复，一轮回复结束后调用
+    pub fn save_output(&self, sample_rate: u32) {
+        spawn_save(self.output_key.clone(), self.output_pcm(), sample_rate);
+    }
+}
+
+fn spawn_save(key: String, pcm: Vec<u8>, sample_rate: u32) {
+    let storage = match RECORDING_STORAGE.as_ref() {
+        Some(storage) => storage.clone(),
+        None => return,
+    };
+    if pcm.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let result = match wav_from_pcm(&pcm, PcmFormat::mono16(sample_rate)) {
+            Ok(wav) => storage.put(&key, wav).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            error!("Failed to save recording {}: {}", key, e);
+        }
+    });
+}
+
+/// 定期删除超过保留天数的录音，并清空 sections 上的引用
+pub fn spawn_retention(db_pool: Pool<ConnectionManager<PgConnection>>) {
+    let storage = match RECORDING_STORAGE.as_ref() {
+        Some(storage) => storage.clone(),
+        None => return,
+    };
+    let retention = match RecordingConfig::load().retention_days {
+        Some(days) => Duration::from_secs(days * 24 * 3600),
+        None => return,
+    };
+
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(RECORDING_RETENTION_CHECK_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = purge_expired(&db_pool, storage.as_ref(), retention).await {
+                error!("Failed to purge expired recordings: {}", e);
+            }
+        }
+    });
+}
+
+/// 按 section_id 分批处理，单个文件删除失败只跳过这个 section，下次再试
+async fn purge_expired(
+    db_pool: &Pool<ConnectionManager<PgConnection>>,
+    storage: &dyn RecordingStorage,
+    retention: Duration,
+) -> Result<()> {
+    let cutoff = SystemTime::now() - retention;
+    let mut last_section_id = String::new();
+    let (mut purged, mut failed) = (0, 0);
+    loop {
+        let expired = sections::table
+            .filter(sections::created_at.lt(cutoff))
+            .filter(sections::section_id.gt(&last_section_id))
+            .filter(
+                sections::input_audio_path
+                    .is_not_null()
+                    .or(sections::output_audio_path.is_not_null()),
+            )
+            .order(sections::section_id.asc())
+            .limit(RECORDING_PURGE_BATCH_SIZE)
+            .select((
+                sections::section_id,
+                sections::input_audio_path,
+                sections::output_audio_path,
+            ))
+            .load::<(String, Option<String>, Option<String>)>(&mut db_pool.get()?)?;
+        let last = match expired.last() {
+            Some((section_id, _, _)) => section_id.clone(),
+            None => break,
+        };
+
+        for (section_id, input, output) in &expired {
+            let mut deleted = true;
+            for key in [input, output].into_iter().flatten() {
+                if let Err(e) = storage.delete(key).await {
+                    error!("Failed to delete recording {}: {}", key, e);
+                    deleted = false;
+                }
+            }
+            if !deleted {
+                failed += 1;
+                continue;
+            }
+            diesel::update(sections::table.filter(sections::section_id.eq(section_id)))
+                .set((
+                    sections::input_audio_path.eq(None::<String>),
+                    sections::output_audio_path.eq(None::<String>),
+                ))
+                .execute(&mut db_pool.get()?)?;
+            purged += 1;
+        }
+        last_section_id = last;
+    }
+    if purged > 0 || failed > 0 {
+        info!(
+            "Purged recordings of {} sections, {} failed",
+            purged, failed
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_storage() {
+        let root = std::env::temp_dir().join(format!("oz_recording_{}", std::process::id()));
+        let storage = LocalStorage::new(&root);
+
+        storage.put("u1/s1/input.wav", vec![1, 2, 3]).await.unwrap();
+        assert_eq!(
+            storage.get("u1/s1/input.wav").await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        storage.delete("u1/s1/input.wav").await.unwrap();
+        assert_eq!(storage.get("u1/s1/input.wav").await.unwrap(), None);
+        // 删除不存在的文件不算错误
+        storage.delete("u1/s1/input.wav").await.unwrap();
+
+        assert!(storage.put("../escape.wav", vec![1]).await.is_err());
+        assert!(storage.get("/etc/passwd").await.is_err());
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_output_kept_in_sentence_order() {
+        let recording = TurnRecording::new("u1", "s1".to_string());
+        assert_eq!(recording.input_key, "u1/s1/input.wav");
+        recording.append_output(1, &[3, 4]);
+        recording.append_output(0, &[1, 2]);
+        recording.append_output(1, &[5]);
+        assert_eq!(recording.output_pcm(), vec![1, 2, 3, 4, 5]);
+    }
+}
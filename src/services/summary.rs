@@ -0,0 +1,168 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use anyhow::Result;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use log::{debug, error};
+
+use crate::constant::{
+    PROMPT_SUMMARIZE_CONVERSATION, PROMPT_SUMMARY_PREFIX, SUMMARY_BATCH_SIZE, SUMMARY_MAX_TOKENS,
+};
+use crate::models::schema;
+use crate::models::section::Section;
+use crate::services::llm::{GenerationParams, LlmMessage, LlmProvider, LlmRequest};
+
+lazy_static::lazy_static! {
+    /// 正在生成摘要的会话，同一个会话同时只跑一个摘要任务
+    static ref SUMMARIZING: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionSummary {
+    pub summary: String,
+    pub summarized_until: SystemTime,
+}
+
+pub fn load_summary(conn: &mut PgConnection, session_id: &str) -> QueryResult<Option<SessionSummary>> {
+    let row = schema::sessions::table
+        .filter(schema::sessions::session_id.eq(session_id))
+        .select((
+            schema::sessions::summary,
+            schema::sessions::summarized_until,
+        ))
+        .first::<(Option<String>, Option<SystemTime>)>(conn)
+        .optional()?;
+
+    Ok(match row {
+        Some((Some(summary), Some(summarized_until))) => Some(SessionSummary {
+            summary,
+            summarized_until,
+        }),
+        _ => None,
+    })
+}
+
+/// 把摘要拼进角色的 system prompt
+pub fn with_summary(prompt: &str, summary: Option<&SessionSummary>) -> String {
+    match summary {
+        Some(summary) if !summary.summary.is_empty() => {
+            format!("{}\n\n{}\n{}", prompt, PROMPT_SUMMARY_PREFIX, summary.summary)
+        }
+        _ => prompt.to_string(),
+    }
+}
+
+fn format_sections(sections: &[Section]) -> String {
+    sections
+        .iter()
+        .map(|s| format!("用户：{}\n助手：{}", s.user_message, s.assistant_message))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// 后台压缩会话中较早的对话，保留最近 keep_recent 轮不动
+pub fn spawn_summarize(
+    db_pool: Pool<ConnectionManager<PgConnection>>,
+    llm: Arc<dyn LlmProvider>,
+    session_id: String,
+    keep_recent: usize,
+    model: Option<String>,
+) {
+    if !SUMMARIZING.lock().unwrap().insert(session_id.clone()) {
+        debug!("session {} is already being summarized", session_id);
+        return;
+    }
+
+    tokio::spawn(async move {
+        // 任务 panic 或被取消时也要释放，否则这个会话再也不会生成摘要
+        let _guard = SummarizingGuard(session_id.clone());
+        if let Err(e) = summarize_session(&db_pool, llm, &session_id, keep_recent, model).await {
+            error!("Failed to summarize session {}: {}", session_id, e);
+        }
+    });
+}
+
+/// 离开作用域时把会话从 SUMMARIZING 中移除
+struct SummarizingGuard(String);
+
+impl Drop for SummarizingGuard {
+    fn drop(&mut self) {
+        SUMMARIZING.lock().unwrap().remove(&self.0);
+    }
+}
+
+async fn summarize_session(
+    db_pool: &Pool<ConnectionManager<PgConnection>>,
+    llm: Arc<dyn LlmProvider>,
+    session_id: &str,
+    keep_recent: usize,
+    model: Option<String>,
+) -> Result<()> {
+    let mut current = load_summary(&mut *db_pool.get()?, session_id)?;
+
+    let mut query = schema::sections::table
+        .filter(schema::sections::session_id.eq(session_id))
+        .into_boxed();
+    if let Some(summary) = &current {
+        query = query.filter(schema::sections::created_at.gt(summary.summarized_until));
+    }
+    let sections = query
+        .order(schema::sections::created_at.asc())
+        .select(Section::as_select())
+        .load(&mut db_pool.get()?)?;
+
+    if sections.len() <= keep_recent {
+        return Ok(());
+    }
+    let pending = &sections[..sections.len() - keep_recent];
+
+    // 分批增量合并，每批结束都落库，中途失败也不会丢掉已经完成的部分
+    for batch in pending.chunks(SUMMARY_BATCH_SIZE as usize) {
+        let previous = current
+            .as_ref()
+            .map(|s| s.summary.as_str())
+            .unwrap_or("无");
+        let request = LlmRequest {
+            messages: vec![
+                LlmMessage::system(PROMPT_SUMMARIZE_CONVERSATION),
+                LlmMessage::user(format!(
+                    "已有摘要：\n{}\n\n新的对话：\n{}",
+                    previous,
+                    format_sections(batch)
+                )),
+            ],
+            params: GenerationParams {
+                model: model.clone(),
+                max_tokens: Some(SUMMARY_MAX_TOKENS),
+                ..Default::default()
+            },
+        };
+        let summary = llm.complete(request).await?;
+        let summarized_until = batch[batch.len() - 1].created_at;
+
+        diesel::update(
+            schema::sessions::table.filter(schema::sessions::session_id.eq(session_id)),
+        )
+        .set((
+            schema::sessions::summary.eq(&summary),
+            schema::sessions::summarized_until.eq(summarized_until),
+            schema::sessions::summary_updated_at.eq(SystemTime::now()),
+        ))
+        .execute(&mut db_pool.get()?)?;
+
+        debug!(
+            "session {} summarized {} more sections",
+            session_id,
+            batch.len()
+        );
+        current = Some(SessionSummary {
+            summary,
+            summarized_until,
+        });
+    }
+
+    Ok(())
+}
@@ -0,0 +1,255 @@
+use serde::Deserialize;
+
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::{
+    DEFAULT_VAD_ENERGY_THRESHOLD_DB, DEFAULT_VAD_MIN_SPEECH_MS, DEFAULT_VAD_SILENCE_MS, VAD_CONFIG,
+};
+
+/// 每帧时长
+const FRAME_MS: u64 = 20;
+/// 连续这么多帧有声音才算开始说话，避免把咔哒声当成说话
+const SPEECH_START_FRAMES: u32 = 3;
+/// 过零率高于这个值的帧更像噪声 (嘶嘶声、风声)
+const MAX_SPEECH_ZCR: f32 = 0.4;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VadConfig {
+    /// 帧能量 (dBFS) 高于这个值才可能是说话
+    #[serde(default = "default_energy_threshold_db")]
+    pub energy_threshold_db: f32,
+    /// 说话后静音多久算一句结束
+    #[serde(default = "default_silence_ms")]
+    pub silence_ms: u64,
+    /// 短于这个时长的声音不算一句话
+    #[serde(default = "default_min_speech_ms")]
+    pub min_speech_ms: u64,
+}
+
+fn default_energy_threshold_db() -> f32 {
+    DEFAULT_VAD_ENERGY_THRESHOLD_DB
+}
+
+fn default_silence_ms() -> u64 {
+    DEFAULT_VAD_SILENCE_MS
+}
+
+fn default_min_speech_ms() -> u64 {
+    DEFAULT_VAD_MIN_SPEECH_MS
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            energy_threshold_db: default_energy_threshold_db(),
+            silence_ms: default_silence_ms(),
+            min_speech_ms: default_min_speech_ms(),
+        }
+    }
+}
+
+impl VadConfig {
+    pub fn load() -> Self {
+        OZ_SERVER_CONFIG
+            .get::<VadConfig>(VAD_CONFIG)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VadEvent {
+    SpeechStarted,
+    /// 说话时长达到 min_speech_ms，可以打断正在播放的回复，短促的噪声不会触发
+    BargeIn,
+    SpeechEnded,
+}
+
+/// 基于帧能量和过零率的简单 VAD，输入 16 位小端单声道 PCM
+pub struct Vad {
+    config: VadConfig,
+    frame_bytes: usize,
+    pending: Vec<u8>,
+    in_speech: bool,
+    voiced_frames: u32,
+    speech_ms: u64,
+    silence_ms: u64,
+    barged_in: bool,
+}
+
+impl Vad {
+    pub fn new(config: VadConfig, sample_rate: u32) -> Self {
+        let frame_samples = (sample_rate as u64 * FRAME_MS / 1000).max(1) as usize;
+        Self {
+            config,
+            frame_bytes: frame_samples * 2,
+            pending: Vec::new(),
+            in_speech: false,
+            voiced_frames: 0,
+            speech_ms: 0,
+            silence_ms: 0,
+            barged_in: false,
+        }
+    }
+
+    pub fn in_speech(&self) -> bool {
+        self.in_speech
+    }
+
+    /// 喂入一段音频，返回这段音频里检测到的事件
+    pub fn process(&mut self, pcm: &[u8]) -> Vec<VadEvent> {
+        self.pending.extend_from_slice(pcm);
+
+        let mut events = Vec::new();
+        let mut offset = 0;
+        while self.pending.len() - offset >= self.frame_bytes {
+            let voiced = self.is_voiced(&self.pending[offset..offset + self.frame_bytes]);
+            offset += self.frame_bytes;
+            self.on_frame(voiced, &mut events);
+        }
+        self.pending.drain(..offset);
+        events
+    }
+
+    fn is_voiced(&self, frame: &[u8]) -> bool {
+        let samples = frame
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / i16::MAX as f32)
+            .collect::<Vec<f32>>();
+        if samples.is_empty() {
+            return false;
+        }
+
+        let rms = (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt();
+        let db = 20.0 * rms.max(1e-9).log10();
+        let crossings = samples
+            .windows(2)
+            .filter(|w| (w[0] >= 0.0) != (w[1] >= 0.0))
+            .count();
+        let zcr = crossings as f32 / samples.len() as f32;
+
+        db >= self.config.energy_threshold_db && zcr <= MAX_SPEECH_ZCR
+    }
+
+    fn on_frame(&mut self, voiced: bool, events: &mut Vec<VadEvent>) {
+        if !self.in_speech {
+            self.voiced_frames = if voiced { self.voiced_frames + 1 } else { 0 };
+            if self.voiced_frames >= SPEECH_START_FRAMES {
+                self.in_speech = true;
+                self.speech_ms = self.voiced_frames as u64 * FRAME_MS;
+                self.silence_ms = 0;
+                events.push(VadEvent::SpeechStarted);
+                self.check_barge_in(events);
+            }
+            return;
+        }
+
+        if voiced {
+            self.speech_ms += FRAME_MS;
+            self.silence_ms = 0;
+            self.check_barge_in(events);
+            return;
+        }
+
+        self.silence_ms += FRAME_MS;
+        if self.silence_ms < self.config.silence_ms {
+            return;
+        }
+
+        // 静音够久了，这段声音太短就当作噪声，重新等待说话
+        let long_enough = self.speech_ms >= self.config.min_speech_ms;
+        self.in_speech = false;
+        self.voiced_frames = 0;
+        self.speech_ms = 0;
+        self.silence_ms = 0;
+        self.barged_in = false;
+        if long_enough {
+            events.push(VadEvent::SpeechEnded);
+        }
+    }
+
+    fn check_barge_in(&mut self, events: &mut Vec<VadEvent>) {
+        if !self.barged_in && self.speech_ms >= self.config.min_speech_ms {
+            self.barged_in = true;
+            events.push(VadEvent::BargeIn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: u32 = 16000;
+
+    fn tone(ms: u64) -> Vec<u8> {
+        let samples = SAMPLE_RATE as u64 * ms / 1000;
+        (0..samples)
+            .flat_map(|i| {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                let s = (t * 220.0 * 2.0 * std::f32::consts::PI).sin() * 8000.0;
+                (s as i16).to_le_bytes()
+            })
+            .collect()
+    }
+
+    fn silence(ms: u64) -> Vec<u8> {
+        vec![0; (SAMPLE_RATE as u64 * ms / 1000 * 2) as usize]
+    }
+
+    fn config() -> VadConfig {
+        VadConfig {
+            energy_threshold_db: -40.0,
+            silence_ms: 400,
+            min_speech_ms: 200,
+        }
+    }
+
+    #[test]
+    fn test_detects_speech_start_and_end() {
+        let mut vad = Vad::new(config(), SAMPLE_RATE);
+        assert!(vad.process(&silence(500)).is_empty());
+        assert_eq!(
+            vad.process(&tone(500)),
+            vec![VadEvent::SpeechStarted, VadEvent::BargeIn]
+        );
+        assert!(vad.in_speech());
+        // 短暂停顿不算结束
+        assert!(vad.process(&silence(200)).is_empty());
+        assert!(vad.process(&tone(100)).is_empty());
+        assert_eq!(vad.process(&silence(500)), vec![VadEvent::SpeechEnded]);
+        assert!(!vad.in_speech());
+    }
+
+    #[test]
+    fn test_ignores_short_noise() {
+        let mut vad = Vad::new(config(), SAMPLE_RATE);
+        assert_eq!(vad.process(&tone(100)), vec![VadEvent::SpeechStarted]);
+        assert!(vad.process(&silence(500)).is_empty());
+        assert!(!vad.in_speech());
+    }
+
+    #[test]
+    fn test_short_noise_does_not_barge_in() {
+        let mut vad = Vad::new(config(), SAMPLE_RATE);
+        // 开口后不到 min_speech_ms 就停了，不应该打断回复
+        let mut events = vad.process(&tone(100));
+        events.extend(vad.process(&silence(500)));
+        assert!(!events.contains(&VadEvent::BargeIn));
+
+        // 说够 min_speech_ms 才打断，且一句话只打断一次
+        let mut events = vad.process(&tone(180));
+        assert_eq!(events, vec![VadEvent::SpeechStarted]);
+        events.extend(vad.process(&tone(300)));
+        assert_eq!(events, vec![VadEvent::SpeechStarted, VadEvent::BargeIn]);
+    }
+
+    #[test]
+    fn test_handles_unaligned_chunks() {
+        let mut vad = Vad::new(config(), SAMPLE_RATE);
+        let audio = tone(300);
+        let mut events = Vec::new();
+        for chunk in audio.chunks(333) {
+            events.extend(vad.process(chunk));
+        }
+        assert_eq!(events, vec![VadEvent::SpeechStarted, VadEvent::BargeIn]);
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use super::{AsrBackend, AsrSession, SpeechChunk, TtsBackend};
+use crate::config::global_cfg::{AsrConfig, TtsConfig};
+use crate::services::metrics::{VOICE_CALL_SECONDS, VOICE_ERRORS_TOTAL};
+
+const OP_ASR_START: &str = "asr_start";
+const OP_ASR_SEND: &str = "asr_send";
+const OP_ASR_FINISH: &str = "asr_finish";
+const OP_TTS: &str = "tts";
+const OP_TTS_SENTENCE: &str = "tts_sentence";
+
+fn observe<T>(backend: &str, op: &str, start: Instant, result: &Result<T>) {
+    observe_outcome(backend, op, start, result.is_ok());
+}
+
+fn observe_outcome(backend: &str, op: &str, start: Instant, ok: bool) {
+    if ok {
+        VOICE_CALL_SECONDS
+            .with_label_values(&[backend, op])
+            .observe(start.elapsed().as_secs_f64());
+    } else {
+        VOICE_ERRORS_TOTAL.with_label_values(&[backend, op]).inc();
+    }
+}
+
+/// 统计建立识别和取结果的耗时与错误
+pub struct InstrumentedAsrBackend {
+    inner: Arc<dyn AsrBackend>,
+}
+
+impl InstrumentedAsrBackend {
+    pub fn new(inner: Arc<dyn AsrBackend>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl AsrBackend for InstrumentedAsrBackend {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn start(&self, config: &AsrConfig) -> Result<Box<dyn AsrSession>> {
+        let backend = self.inner.name();
+        let start = Instant::now();
+        let result = self.inner.start(config).await;
+        observe(backend, OP_ASR_START, start, &result);
+        Ok(Box::new(InstrumentedAsrSession {
+            inner: result?,
+            backend,
+        }))
+    }
+}
+
+struct InstrumentedAsrSession {
+    inner: Box<dyn AsrSession>,
+    backend: &'static str,
+}
+
+#[async_trait]
+impl AsrSession for InstrumentedAsrSession {
+    async fn send_audio(&mut self, audio: &[u8]) -> Result<()> {
+        let result = self.inner.send_audio(audio).await;
+        if result.is_err() {
+            VOICE_ERRORS_TOTAL
+                .with_label_values(&[self.backend, OP_ASR_SEND])
+                .inc();
+        }
+        result
+    }
+
+    fn take_partials(&mut self) -> Option<mpsc::Receiver<String>> {
+        self.inner.take_partials()
+    }
+
+    async fn finish(&mut self) -> Result<String> {
+        let start = Instant::now();
+        let result = self.inner.finish().await;
+        observe(self.backend, OP_ASR_FINISH, start, &result);
+        result
+    }
+}
+
+/// 统计建立合成流的耗时与错误。直接返回后端的 receiver，不再转发一次，
+/// 消费方的背压原样传给后端；一句话合成完的耗时由消费方调用 observe_tts_sentence
+pub struct InstrumentedTtsBackend {
+    inner: Arc<dyn TtsBackend>,
+}
+
+impl InstrumentedTtsBackend {
+    pub fn new(inner: Arc<dyn TtsBackend>) -> Self {
+        Self { inner }
+    }
+}
+
+#[async_trait]
+impl TtsBackend for InstrumentedTtsBackend {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    async fn synthesize(
+        &self,
+        text: &str,
+        config: &TtsConfig,
+    ) -> Result<mpsc::Receiver<SpeechChunk>> {
+        let backend = self.inner.name();
+        let start = Instant::now();
+        let result = self.inner.synthesize(text, config).await;
+        observe(backend, OP_TTS, start, &result);
+        result
+    }
+}
+
+/// 一句话从请求合成到收到最后一段音频的耗时，没收到最后一段就断开算错误
+pub fn observe_tts_sentence(backend: &str, start: Instant, finished: bool) {
+    observe_outcome(backend, OP_TTS_SENTENCE, start, finished);
+}
@@ -0,0 +1,185 @@
+use std::f32::consts::PI;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::mpsc;
+
+use super::{AsrBackend, AsrSession, SpeechChunk, TtsBackend, ASR_PARTIAL_BUFFER};
+use crate::config::global_cfg::{AsrConfig, TtsConfig};
+
+const DEFAULT_MOCK_SAMPLE_RATE: u32 = 16000;
+// 每个字对应的音频时长
+const MOCK_MS_PER_CHAR: u32 = 200;
+const MOCK_CHUNK_MS: u32 = 100;
+const MOCK_TONE_HZ: f32 = 440.0;
+const MOCK_TONE_AMPLITUDE: f32 = 3000.0;
+
+/// 进程内的确定性识别，不访问网络，用于测试。
+/// 配置了 transcript 文件时每轮都读取文件内容作为结果，否则返回收到的音频字节数；
+/// 每收到一段音频给出一条中间结果，内容是目前收到的字节数
+pub struct MockAsrBackend {
+    transcript_file: Option<String>,
+}
+
+impl MockAsrBackend {
+    pub fn new(transcript_file: Option<String>) -> Self {
+        Self { transcript_file }
+    }
+}
+
+struct MockAsrSession {
+    transcript_file: Option<String>,
+    received: usize,
+    partial_sender: mpsc::Sender<String>,
+    partials: Option<mpsc::Receiver<String>>,
+}
+
+fn received_text(received: usize) -> String {
+    format!("收到{}字节音频", received)
+}
+
+#[async_trait]
+impl AsrSession for MockAsrSession {
+    async fn send_audio(&mut self, audio: &[u8]) -> Result<()> {
+        self.received += audio.len();
+        let _ = self.partial_sender.try_send(received_text(self.received));
+        Ok(())
+    }
+
+    fn take_partials(&mut self) -> Option<mpsc::Receiver<String>> {
+        self.partials.take()
+    }
+
+    async fn finish(&mut self) -> Result<String> {
+        match &self.transcript_file {
+            Some(path) => Ok(tokio::fs::read_to_string(path).await?.trim().to_string()),
+            None => Ok(received_text(self.received)),
+        }
+    }
+}
+
+#[async_trait]
+impl AsrBackend for MockAsrBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn start(&self, _config: &AsrConfig) -> Result<Box<dyn AsrSession>> {
+        let (partial_sender, partials) = mpsc::channel(ASR_PARTIAL_BUFFER);
+        Ok(Box::new(MockAsrSession {
+            transcript_file: self.transcript_file.clone(),
+            received: 0,
+            partial_sender,
+            partials: Some(partials),
+        }))
+    }
+}
+
+/// 进程内的确定性合成，输出 16 位单声道 PCM 正弦音，时长和字数成正比
+pub struct MockTtsBackend {
+    sample_rate: u32,
+}
+
+impl MockTtsBackend {
+    pub fn new(sample_rate: Option<u32>) -> Self {
+        Self {
+            sample_rate: sample_rate.unwrap_or(DEFAULT_MOCK_SAMPLE_RATE),
+        }
+    }
+
+    fn tone(&self, text: &str) -> Vec<u8> {
+        let duration_ms = text.chars().count() as u32 * MOCK_MS_PER_CHAR;
+        let samples = (self.sample_rate as u64 * duration_ms as u64 / 1000) as usize;
+        (0..samples)
+            .flat_map(|i| {
+                let t = i as f32 / self.sample_rate as f32;
+                let sample = (2.0 * PI * MOCK_TONE_HZ * t).sin() * MOCK_TONE_AMPLITUDE;
+                (sample as i16).to_le_bytes()
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl TtsBackend for MockTtsBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    async fn synthesize(
+        &self,
+        text: &str,
+        config: &TtsConfig,
+    ) -> Result<mpsc::Receiver<SpeechChunk>> {
+        if config.enc_format != "pcm" {
+            return Err(anyhow::anyhow!(
+                "Mock tts only supports pcm, got {}",
+                config.enc_format
+            ));
+        }
+
+        let audio = self.tone(text);
+        let chunk_len = (self.sample_rate * MOCK_CHUNK_MS / 1000) as usize * 2;
+        let chunks = audio.chunks(chunk_len.max(2)).collect::<Vec<&[u8]>>();
+        let (sender, receiver) = mpsc::channel(chunks.len().max(1));
+        if chunks.is_empty() {
+            let _ = sender.try_send(SpeechChunk {
+                audio: Vec::new(),
+                is_last: true,
+            });
+        }
+        for (i, chunk) in chunks.iter().enumerate() {
+            let _ = sender.try_send(SpeechChunk {
+                audio: chunk.to_vec(),
+                is_last: i + 1 == chunks.len(),
+            });
+        }
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_asr_without_transcript() {
+        let backend = MockAsrBackend::new(None);
+        let mut session = backend.start(&AsrConfig::default()).await.unwrap();
+        let mut partials = session.take_partials().unwrap();
+        assert!(session.take_partials().is_none());
+        session.send_audio(&[0; 320]).await.unwrap();
+        session.send_audio(&[0; 320]).await.unwrap();
+        assert_eq!(partials.recv().await.unwrap(), "收到320字节音频");
+        assert_eq!(partials.recv().await.unwrap(), "收到640字节音频");
+        assert_eq!(session.finish().await.unwrap(), "收到640字节音频");
+    }
+
+    #[tokio::test]
+    async fn test_mock_tts_length_follows_text() {
+        let backend = MockTtsBackend::new(Some(16000));
+        let config = TtsConfig::default();
+        let mut receiver = backend.synthesize("你好", &config).await.unwrap();
+
+        let mut audio = Vec::new();
+        let mut last_seen = false;
+        while let Some(chunk) = receiver.recv().await {
+            assert!(!last_seen);
+            audio.extend_from_slice(&chunk.audio);
+            last_seen = chunk.is_last;
+        }
+        assert!(last_seen);
+        // 两个字 400ms，16kHz 16 位
+        assert_eq!(audio.len(), 16000 * 2 * 400 / 1000);
+
+        let mp3 = TtsConfig {
+            enc_format: "mp3".to_string(),
+            ..TtsConfig::default()
+        };
+        assert!(backend.synthesize("你好", &mp3).await.is_err());
+    }
+}
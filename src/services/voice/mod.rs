@@ -0,0 +1,117 @@
+pub mod instrumented;
+pub mod mock;
+pub mod volc;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use config::Config;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::config::global_cfg::{AsrConfig, TtsConfig};
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::{VOICE_CONFIG, VOICE_PROVIDER_MOCK, VOICE_PROVIDER_VOLCANO};
+
+/// 合成出的一段音频
+#[derive(Debug, Clone)]
+pub struct SpeechChunk {
+    pub audio: Vec<u8>,
+    pub is_last: bool,
+}
+
+/// 识别中间结果最多缓存的条数，取得慢时丢掉新的
+pub const ASR_PARTIAL_BUFFER: usize = 16;
+
+/// 一轮识别：边收音频边识别，说完后取整句结果
+#[async_trait]
+pub trait AsrSession: Send {
+    async fn send_audio(&mut self, audio: &[u8]) -> Result<()>;
+
+    /// 识别中间结果 (当前的整句文字)，只能取一次，后端不支持时返回 None
+    fn take_partials(&mut self) -> Option<mpsc::Receiver<String>> {
+        None
+    }
+
+    async fn finish(&mut self) -> Result<String>;
+}
+
+#[async_trait]
+pub trait AsrBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn start(&self, config: &AsrConfig) -> Result<Box<dyn AsrSession>>;
+}
+
+#[async_trait]
+pub trait TtsBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// 输出 PCM (16 位单声道) 时的采样率
+    fn sample_rate(&self) -> u32;
+
+    /// 合成一段文字，音频按顺序从 channel 中取出，最后一块 is_last 为 true
+    async fn synthesize(
+        &self,
+        text: &str,
+        config: &TtsConfig,
+    ) -> Result<mpsc::Receiver<SpeechChunk>>;
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VoiceConfig {
+    pub asr_provider: Option<String>,
+    pub tts_provider: Option<String>,
+    /// 仅 mock ASR 使用，每轮识别时读取这个文件的内容作为结果
+    pub mock_transcript_file: Option<String>,
+    /// 仅 mock TTS 使用，输出 PCM 的采样率
+    pub mock_sample_rate: Option<u32>,
+}
+
+fn voice_config(config: &Config) -> VoiceConfig {
+    config.get::<VoiceConfig>(VOICE_CONFIG).unwrap_or_default()
+}
+
+pub fn build_asr_backend(config: &Config) -> Result<Arc<dyn AsrBackend>> {
+    let voice_config = voice_config(config);
+    let provider = voice_config
+        .asr_provider
+        .unwrap_or(VOICE_PROVIDER_VOLCANO.to_string());
+
+    match provider.as_str() {
+        VOICE_PROVIDER_VOLCANO => Ok(Arc::new(volc::VolcanoAsrBackend)),
+        VOICE_PROVIDER_MOCK => Ok(Arc::new(mock::MockAsrBackend::new(
+            voice_config.mock_transcript_file,
+        ))),
+        other => Err(anyhow::anyhow!("Unknown asr provider: {}", other)),
+    }
+}
+
+pub fn build_tts_backend(config: &Config) -> Result<Arc<dyn TtsBackend>> {
+    let voice_config = voice_config(config);
+    let provider = voice_config
+        .tts_provider
+        .unwrap_or(VOICE_PROVIDER_VOLCANO.to_string());
+
+    match provider.as_str() {
+        VOICE_PROVIDER_VOLCANO => Ok(Arc::new(volc::VolcanoTtsBackend)),
+        VOICE_PROVIDER_MOCK => Ok(Arc::new(mock::MockTtsBackend::new(
+            voice_config.mock_sample_rate,
+        ))),
+        other => Err(anyhow::anyhow!("Unknown tts provider: {}", other)),
+    }
+}
+
+lazy_static::lazy_static! {
+    pub static ref ASR_BACKEND: Arc<dyn AsrBackend> = Arc::new(
+        instrumented::InstrumentedAsrBackend::new(
+            build_asr_backend(&OZ_SERVER_CONFIG).expect("Failed to build asr backend"),
+        ),
+    );
+    pub static ref TTS_BACKEND: Arc<dyn TtsBackend> = Arc::new(
+        instrumented::InstrumentedTtsBackend::new(
+            build_tts_backend(&OZ_SERVER_CONFIG).expect("Failed to build tts backend"),
+        ),
+    );
+}
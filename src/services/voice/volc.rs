@@ -0,0 +1,430 @@
+use std::io::{Read, Write};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use super::{AsrBackend, AsrSession, SpeechChunk, TtsBackend, ASR_PARTIAL_BUFFER};
+use crate::config::global_cfg::{AsrConfig, TtsConfig};
+
+const VOLCANO_ASR_URL: &str = "wss://openspeech.bytedance.com/api/v2/asr";
+const VOLCANO_TTS_URL: &str = "wss://openspeech.bytedance.com/api/v1/tts/ws_binary";
+
+// 每段合成结果最多缓存的音频块数
+const SPEECH_CHUNK_BUFFER: usize = 64;
+// 火山合成 PCM 的默认采样率
+const VOLCANO_TTS_SAMPLE_RATE: u32 = 24000;
+// 识别成功的返回码
+const VOLCANO_ASR_SUCCESS: i64 = 1000;
+
+// 二进制协议头: 版本 1，头长 1 (4 字节)
+const PROTOCOL_VERSION_AND_HEADER_SIZE: u8 = 0x11;
+const FULL_CLIENT_REQUEST: u8 = 0b0001;
+const AUDIO_ONLY_REQUEST: u8 = 0b0010;
+const FULL_SERVER_RESPONSE: u8 = 0b1001;
+const SERVER_ACK: u8 = 0b1011;
+const SERVER_ERROR_RESPONSE: u8 = 0b1111;
+// 最后一包音频
+const NEG_SEQUENCE: u8 = 0b0010;
+const JSON_SERIALIZATION: u8 = 0b0001;
+const NO_SERIALIZATION: u8 = 0b0000;
+const GZIP_COMPRESSION: u8 = 0b0001;
+
+type VolcanoStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = GzDecoder::new(data);
+    let mut output = Vec::new();
+    decoder.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// 4 字节头 + 4 字节负载长度 + gzip 负载
+fn encode_frame(message_type: u8, flags: u8, serialization: u8, payload: &[u8]) -> Result<Vec<u8>> {
+    let payload = gzip(payload)?;
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.push(PROTOCOL_VERSION_AND_HEADER_SIZE);
+    frame.push(message_type << 4 | flags);
+    frame.push(serialization << 4 | GZIP_COMPRESSION);
+    frame.push(0);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// 服务端返回的一帧
+#[derive(Debug, PartialEq)]
+enum ServerFrame {
+    Full(Vec<u8>),
+    /// 合成的音频，sequence 为负表示最后一块；没有 sequence 的确认帧为 None
+    Audio(Option<i32>, Vec<u8>),
+    Error(u32, String),
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or_else(|| anyhow::anyhow!("Volcano frame too short"))
+}
+
+fn decode_frame(data: &[u8]) -> Result<ServerFrame> {
+    if data.len() < 4 {
+        return Err(anyhow::anyhow!("Volcano frame too short"));
+    }
+    let header_size = (data[0] & 0x0f) as usize * 4;
+    let message_type = data[1] >> 4;
+    let flags = data[1] & 0x0f;
+    let compressed = data[2] & 0x0f == GZIP_COMPRESSION;
+    let payload = data
+        .get(header_size..)
+        .ok_or_else(|| anyhow::anyhow!("Volcano frame too short"))?;
+    let body = |offset: usize| -> Result<Vec<u8>> {
+        let size = read_u32(payload, offset)? as usize;
+        let body = payload
+            .get(offset + 4..offset + 4 + size)
+            .ok_or_else(|| anyhow::anyhow!("Volcano frame too short"))?;
+        if compressed {
+            gunzip(body)
+        } else {
+            Ok(body.to_vec())
+        }
+    };
+
+    match message_type {
+        FULL_SERVER_RESPONSE => Ok(ServerFrame::Full(body(0)?)),
+        SERVER_ACK if flags == 0 => Ok(ServerFrame::Audio(None, Vec::new())),
+        SERVER_ACK => {
+            let sequence = read_u32(payload, 0)? as i32;
+            let audio = if payload.len() > 4 {
+                body(4)?
+            } else {
+                Vec::new()
+            };
+            Ok(ServerFrame::Audio(Some(sequence), audio))
+        }
+        SERVER_ERROR_RESPONSE => {
+            let code = read_u32(payload, 0)?;
+            let message = String::from_utf8_lossy(&body(4)?).to_string();
+            Ok(ServerFrame::Error(code, message))
+        }
+        other => Err(anyhow::anyhow!("Unknown volcano message type: {}", other)),
+    }
+}
+
+async fn connect(url: &str, token: &str) -> Result<VolcanoStream> {
+    if token.is_empty() {
+        return Err(anyhow::anyhow!("Volcano token is not configured"));
+    }
+    let mut request = url.into_client_request()?;
+    request.headers_mut().insert(
+        "Authorization",
+        HeaderValue::from_str(&format!("Bearer; {}", token))?,
+    );
+    let (stream, _) = tokio_tungstenite::connect_async(request).await?;
+    Ok(stream)
+}
+
+/// 火山流式识别
+pub struct VolcanoAsrBackend;
+
+struct VolcanoAsrSession {
+    sink: SplitSink<VolcanoStream, Message>,
+    result: Option<oneshot::Receiver<Result<String>>>,
+    partials: Option<mpsc::Receiver<String>>,
+}
+
+/// 解析识别结果，返回当前整句文字和是否为最终结果
+fn parse_asr_response(payload: &[u8]) -> Result<(String, bool)> {
+    let response: Value = serde_json::from_slice(payload)?;
+    let code = response["code"].as_i64().unwrap_or_default();
+    if code != VOLCANO_ASR_SUCCESS {
+        return Err(anyhow::anyhow!(
+            "Volcano asr failed: {} {}",
+            code,
+            response["message"].as_str().unwrap_or_default()
+        ));
+    }
+    let text = response["result"][0]["text"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let is_final = response["sequence"].as_i64().unwrap_or_default() < 0;
+    Ok((text, is_final))
+}
+
+/// 读到最终结果为止，中间结果发给 partials，没人取时直接丢掉
+async fn read_asr_result(
+    mut stream: SplitStream<VolcanoStream>,
+    partials: mpsc::Sender<String>,
+) -> Result<String> {
+    let mut text = String::new();
+    while let Some(message) = stream.next().await {
+        let data = match message? {
+            Message::Binary(data) => data,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        match decode_frame(&data)? {
+            ServerFrame::Full(payload) => {
+                let (current, is_final) = parse_asr_response(&payload)?;
+                text = current;
+                if is_final {
+                    return Ok(text);
+                }
+                if !text.is_empty() {
+                    let _ = partials.try_send(text.clone());
+                }
+            }
+            ServerFrame::Error(code, message) => {
+                return Err(anyhow::anyhow!("Volcano asr error {}: {}", code, message))
+            }
+            ServerFrame::Audio(..) => {}
+        }
+    }
+    Ok(text)
+}
+
+#[async_trait]
+impl AsrSession for VolcanoAsrSession {
+    async fn send_audio(&mut self, audio: &[u8]) -> Result<()> {
+        let frame = encode_frame(AUDIO_ONLY_REQUEST, 0, NO_SERIALIZATION, audio)?;
+        self.sink.send(Message::Binary(frame.into())).await?;
+        Ok(())
+    }
+
+    fn take_partials(&mut self) -> Option<mpsc::Receiver<String>> {
+        self.partials.take()
+    }
+
+    async fn finish(&mut self) -> Result<String> {
+        let frame = encode_frame(AUDIO_ONLY_REQUEST, NEG_SEQUENCE, NO_SERIALIZATION, &[])?;
+        self.sink.send(Message::Binary(frame.into())).await?;
+        let result = self
+            .result
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Volcano asr already finished"))?;
+        let text = result.await??;
+        let _ = self.sink.close().await;
+        Ok(text)
+    }
+}
+
+#[async_trait]
+impl AsrBackend for VolcanoAsrBackend {
+    fn name(&self) -> &'static str {
+        "volcano"
+    }
+
+    async fn start(&self, config: &AsrConfig) -> Result<Box<dyn AsrSession>> {
+        let stream = connect(VOLCANO_ASR_URL, &config.token).await?;
+        let (mut sink, stream) = stream.split();
+
+        let request = json!({
+            "app": {
+                "appid": config.app_id,
+                "cluster": config.cluster,
+                "token": config.token,
+            },
+            "user": { "uid": crate::utils::gen_new_id() },
+            "request": {
+                "reqid": uuid::Uuid::new_v4().to_string(),
+                "nbest": 1,
+                "workflow": config.workflow,
+                "show_utterances": false,
+                "result_type": "full",
+                "sequence": 1,
+            },
+            "audio": {
+                "format": config.audio_format,
+                "rate": config.sample_rate,
+                "bits": 16,
+                "channel": 1,
+                "codec": config.codec,
+            },
+        });
+        let frame = encode_frame(
+            FULL_CLIENT_REQUEST,
+            0,
+            JSON_SERIALIZATION,
+            request.to_string().as_bytes(),
+        )?;
+        sink.send(Message::Binary(frame.into())).await?;
+
+        let (sender, result) = oneshot::channel();
+        let (partial_sender, partials) = mpsc::channel(ASR_PARTIAL_BUFFER);
+        tokio::spawn(async move {
+            let _ = sender.send(read_asr_result(stream, partial_sender).await);
+        });
+        Ok(Box::new(VolcanoAsrSession {
+            sink,
+            result: Some(result),
+            partials: Some(partials),
+        }))
+    }
+}
+
+/// 火山流式合成。一个连接只合成一段文字，所以每段都要新建连接
+pub struct VolcanoTtsBackend;
+
+#[async_trait]
+impl TtsBackend for VolcanoTtsBackend {
+    fn name(&self) -> &'static str {
+        "volcano"
+    }
+
+    fn sample_rate(&self) -> u32 {
+        VOLCANO_TTS_SAMPLE_RATE
+    }
+
+    async fn synthesize(
+        &self,
+        text: &str,
+        config: &TtsConfig,
+    ) -> Result<mpsc::Receiver<SpeechChunk>> {
+        let mut stream = connect(VOLCANO_TTS_URL, &config.token).await?;
+        let request = json!({
+            "app": {
+                "appid": config.app_id,
+                "token": config.token,
+                "cluster": config.cluster,
+            },
+            "user": { "uid": crate::utils::gen_new_id() },
+            "audio": {
+                "voice_type": config.voice_type,
+                "encoding": config.enc_format,
+                "rate": VOLCANO_TTS_SAMPLE_RATE,
+                "speed_ratio": 1.0,
+            },
+            "request": {
+                "reqid": uuid::Uuid::new_v4().to_string(),
+                "text": text,
+                "text_type": "plain",
+                "operation": "submit",
+            },
+        });
+        let frame = encode_frame(
+            FULL_CLIENT_REQUEST,
+            0,
+            JSON_SERIALIZATION,
+            request.to_string().as_bytes(),
+        )?;
+        stream.send(Message::Binary(frame.into())).await?;
+
+        let (sender, receiver) = mpsc::channel(SPEECH_CHUNK_BUFFER);
+        tokio::spawn(async move {
+            while let Some(message) = stream.next().await {
+                let data = match message {
+                    Ok(Message::Binary(data)) => data,
+                    Ok(Message::Close(_)) => break,
+                    Ok(_) => continue,
+                    Err(e) => {
+                        log::error!("Volcano tts connection failed: {}", e);
+                        break;
+                    }
+                };
+                let (sequence, audio) = match decode_frame(&data) {
+                    Ok(ServerFrame::Audio(Some(sequence), audio)) => (sequence, audio),
+                    Ok(ServerFrame::Error(code, message)) => {
+                        log::error!("Volcano tts error {}: {}", code, message);
+                        break;
+                    }
+                    Ok(_) => continue,
+                    Err(e) => {
+                        log::error!("Failed to decode volcano tts frame: {}", e);
+                        break;
+                    }
+                };
+                let is_last = sequence < 0;
+                let chunk = SpeechChunk { audio, is_last };
+                if sender.send(chunk).await.is_err() || is_last {
+                    break;
+                }
+            }
+            let _ = stream.close(None).await;
+        });
+        Ok(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_frame(message_type: u8, flags: u8, prefix: &[u8], payload: &[u8]) -> Vec<u8> {
+        let payload = gzip(payload).unwrap();
+        let mut frame = vec![
+            PROTOCOL_VERSION_AND_HEADER_SIZE,
+            message_type << 4 | flags,
+            JSON_SERIALIZATION << 4 | GZIP_COMPRESSION,
+            0,
+        ];
+        frame.extend_from_slice(prefix);
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    #[test]
+    fn test_encode_frame_header() {
+        let frame =
+            encode_frame(AUDIO_ONLY_REQUEST, NEG_SEQUENCE, NO_SERIALIZATION, b"pcm").unwrap();
+        assert_eq!(&frame[..4], &[0x11, 0x22, 0x01, 0x00]);
+        let size = read_u32(&frame, 4).unwrap() as usize;
+        assert_eq!(frame.len(), 8 + size);
+        assert_eq!(gunzip(&frame[8..]).unwrap(), b"pcm");
+    }
+
+    #[test]
+    fn test_decode_asr_response() {
+        let payload = r#"{"code":1000,"sequence":-3,"result":[{"text":"你好"}]}"#.as_bytes();
+        let frame = server_frame(FULL_SERVER_RESPONSE, 0, &[], payload);
+        let ServerFrame::Full(payload) = decode_frame(&frame).unwrap() else {
+            panic!("expected full response");
+        };
+        assert_eq!(
+            parse_asr_response(&payload).unwrap(),
+            ("你好".to_string(), true)
+        );
+
+        let failed = br#"{"code":1013,"message":"silence"}"#;
+        assert!(parse_asr_response(failed).is_err());
+    }
+
+    #[test]
+    fn test_decode_tts_audio_and_error() {
+        let ack = [PROTOCOL_VERSION_AND_HEADER_SIZE, SERVER_ACK << 4, 0x11, 0];
+        assert_eq!(
+            decode_frame(&ack).unwrap(),
+            ServerFrame::Audio(None, Vec::new())
+        );
+
+        let last = server_frame(SERVER_ACK, 0b0011, &(-2i32).to_be_bytes(), b"audio");
+        assert_eq!(
+            decode_frame(&last).unwrap(),
+            ServerFrame::Audio(Some(-2), b"audio".to_vec())
+        );
+
+        let error = server_frame(SERVER_ERROR_RESPONSE, 0, &3001u32.to_be_bytes(), b"bad");
+        assert_eq!(
+            decode_frame(&error).unwrap(),
+            ServerFrame::Error(3001, "bad".to_string())
+        );
+    }
+}
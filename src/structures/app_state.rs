@@ -1,15 +1,25 @@
 use config::Config;
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::PgConnection;
+use std::sync::Arc;
+
+use crate::config::global_cfg::GlobalConfig;
+use crate::config::GLOBAL_CONFIG;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: Pool<ConnectionManager<PgConnection>>,
     pub config: Config,
+    /// ASR/TTS 等类型化配置
+    pub global_config: Arc<GlobalConfig>,
 }
 
 impl AppState {
     pub fn new(db_pool: Pool<ConnectionManager<PgConnection>>, config: Config) -> Self {
-        Self { db_pool, config }
+        Self {
+            db_pool,
+            config,
+            global_config: GLOBAL_CONFIG.clone(),
+        }
     }
 }
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+use crate::structures::user::TokenPayload;
+
+#[derive(Deserialize)]
+pub struct PairingRequest {
+    pub device_id: String,
+    pub name: Option<String>,
+    // 已配对的设备重新配对时必须带上当前的设备凭证
+    pub device_secret: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PairingPayload {
+    pub code: String,
+    pub pairing_secret: String,
+    pub expires_in: u64,
+}
+
+#[derive(Serialize)]
+pub struct PairingResponse {
+    pub code: i32,
+    pub msg: String,
+    pub payload: Option<PairingPayload>,
+}
+
+impl PairingResponse {
+    pub fn success(payload: PairingPayload) -> Self {
+        Self {
+            code: 0,
+            msg: "ok".to_string(),
+            payload: Some(payload),
+        }
+    }
+
+    pub fn error(msg: &str) -> Self {
+        Self {
+            code: -1,
+            msg: msg.to_string(),
+            payload: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PairingPollRequest {
+    pub device_id: String,
+    pub pairing_secret: String,
+}
+
+#[derive(Serialize)]
+pub struct PairingPollPayload {
+    pub status: String,
+    // 只在配对完成的那一次轮询中返回，之后设备用它换取 token
+    pub device_secret: Option<String>,
+    pub tokens: Option<TokenPayload>,
+}
+
+#[derive(Serialize)]
+pub struct PairingPollResponse {
+    pub code: i32,
+    pub msg: String,
+    pub payload: Option<PairingPollPayload>,
+}
+
+impl PairingPollResponse {
+    pub fn success(payload: PairingPollPayload) -> Self {
+        Self {
+            code: 0,
+            msg: "ok".to_string(),
+            payload: Some(payload),
+        }
+    }
+
+    pub fn error(msg: &str) -> Self {
+        Self {
+            code: -1,
+            msg: msg.to_string(),
+            payload: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConfirmPairingRequest {
+    pub device_id: String,
+    pub code: String,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct DeviceTokenRequest {
+    pub device_id: String,
+    pub device_secret: String,
+}
+
+#[derive(Serialize)]
+pub struct DeviceInfo {
+    pub id: String,
+    pub name: String,
+    pub paired_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct DeviceListResponse {
+    pub code: i32,
+    pub msg: String,
+    pub devices: Vec<DeviceInfo>,
+}
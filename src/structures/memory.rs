@@ -0,0 +1,26 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize)]
+pub struct ListMemoriesQuery {
+    pub role_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateMemoryRequest {
+    pub content: String,
+}
+
+#[derive(Serialize)]
+pub struct MemoryInfo {
+    pub id: String,
+    pub role_id: String,
+    pub content: String,
+    pub updated_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct MemoryListResponse {
+    pub code: i32,
+    pub msg: String,
+    pub memories: Vec<MemoryInfo>,
+}
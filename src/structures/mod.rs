@@ -4,3 +4,5 @@ pub use app_state::AppState;
 pub use role::*;
 pub mod user;
 pub mod app_error;
+pub mod device;
+pub mod memory;
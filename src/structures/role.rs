@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::services::llm::GenerationParams;
+
 #[derive(Serialize)]
 pub struct RoleResponse {
     pub code: i32,
@@ -76,6 +78,19 @@ pub struct CreateRoleRequest {
     pub my_story: String,
     pub voice_id: String,
     pub preference: String,
+    #[serde(default)]
+    pub generation: Option<GenerationParams>,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateRoleRequest {
+    pub role_id: String,
+    pub name: Option<String>,
+    pub prompt: Option<String>,
+    pub voice_id: Option<String>,
+    /// 提供时整体替换角色的生成参数
+    pub generation: Option<GenerationParams>,
+    pub context_token_budget: Option<u32>,
 }
 
 #[derive(Serialize)]
@@ -88,6 +103,7 @@ pub struct CreateRolePayload {
     pub my_story: String,
     pub voice_id: String,
     pub preference: String,
+    pub generation: GenerationParams,
 }
 
 #[derive(Serialize)]
@@ -1,4 +1,75 @@
+use serde::{Deserialize, Serialize};
+
 #[derive(Clone)]
 pub struct CurrentUser {
-    pub user_id: String, // 无所吊谓，可能是dev_id，也可能是user_id,总之，把它当作user_id用。
+    pub user_id: String, // 设备 token 中也是其绑定的用户 id
+    pub device_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuthErrorResponse {
+    pub code: i32,
+    pub msg: String,
+    pub reason: String,
+}
+
+impl AuthErrorResponse {
+    pub fn new(reason: &str, msg: &str) -> Self {
+        Self {
+            code: -1,
+            msg: msg.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize)]
+pub struct TokenPayload {
+    pub user_id: String,
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize)]
+pub struct TokenResponse {
+    pub code: i32,
+    pub msg: String,
+    pub payload: Option<TokenPayload>,
+}
+
+impl TokenResponse {
+    pub fn success(payload: TokenPayload) -> Self {
+        Self {
+            code: 0,
+            msg: "ok".to_string(),
+            payload: Some(payload),
+        }
+    }
+
+    pub fn error(msg: &str) -> Self {
+        Self {
+            code: -1,
+            msg: msg.to_string(),
+            payload: None,
+        }
+    }
 }
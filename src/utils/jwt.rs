@@ -0,0 +1,165 @@
+use crate::config::OZ_SERVER_CONFIG;
+use crate::constant::{
+    DEFAULT_ACCESS_TOKEN_TTL_SECS, DEFAULT_REFRESH_TOKEN_TTL_SECS, JWT_ACCESS_TOKEN_TTL,
+    JWT_REFRESH_TOKEN_TTL, JWT_SECRET,
+};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+pub const TOKEN_TYPE_ACCESS: &str = "access";
+pub const TOKEN_TYPE_REFRESH: &str = "refresh";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// user id
+    pub sub: String,
+    /// device id the token was issued to, if any
+    pub dev: Option<String>,
+    pub typ: String,
+    pub jti: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenError {
+    #[error("token expired")]
+    Expired,
+    #[error("invalid token: {0}")]
+    Invalid(String),
+    #[error("unexpected token type: {0}")]
+    WrongType(String),
+    #[error("jwt secret is not configured")]
+    MissingSecret,
+}
+
+impl TokenError {
+    /// machine readable reason, returned to clients in the 401 body
+    pub fn reason(&self) -> &'static str {
+        match self {
+            TokenError::Expired => "token_expired",
+            TokenError::Invalid(_) | TokenError::WrongType(_) => "token_invalid",
+            TokenError::MissingSecret => "auth_unavailable",
+        }
+    }
+}
+
+impl Claims {
+    pub fn new(user_id: &str, device_id: Option<&str>, typ: &str, ttl_secs: i64) -> Self {
+        let now = chrono::Utc::now().timestamp();
+        Self {
+            sub: user_id.to_string(),
+            dev: device_id.map(|d| d.to_string()),
+            typ: typ.to_string(),
+            jti: xid::new().to_string(),
+            iat: now,
+            exp: now + ttl_secs,
+        }
+    }
+}
+
+pub fn encode_claims(claims: &Claims, secret: &str) -> Result<String, TokenError> {
+    encode(
+        &Header::new(Algorithm::HS256),
+        claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .map_err(|e| TokenError::Invalid(e.to_string()))
+}
+
+pub fn decode_claims(token: &str, secret: &str, expected_type: &str) -> Result<Claims, TokenError> {
+    let validation = Validation::new(Algorithm::HS256);
+    let data = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|e| match e.kind() {
+        ErrorKind::ExpiredSignature => TokenError::Expired,
+        _ => TokenError::Invalid(e.to_string()),
+    })?;
+
+    if data.claims.typ != expected_type {
+        return Err(TokenError::WrongType(data.claims.typ));
+    }
+
+    Ok(data.claims)
+}
+
+fn jwt_secret() -> Result<String, TokenError> {
+    match OZ_SERVER_CONFIG.get::<String>(JWT_SECRET) {
+        Ok(secret) if !secret.is_empty() => Ok(secret),
+        _ => Err(TokenError::MissingSecret),
+    }
+}
+
+pub fn access_token_ttl() -> i64 {
+    OZ_SERVER_CONFIG
+        .get::<i64>(JWT_ACCESS_TOKEN_TTL)
+        .unwrap_or(DEFAULT_ACCESS_TOKEN_TTL_SECS)
+}
+
+pub fn refresh_token_ttl() -> i64 {
+    OZ_SERVER_CONFIG
+        .get::<i64>(JWT_REFRESH_TOKEN_TTL)
+        .unwrap_or(DEFAULT_REFRESH_TOKEN_TTL_SECS)
+}
+
+pub fn issue_access_token(user_id: &str, device_id: Option<&str>) -> Result<String, TokenError> {
+    let claims = Claims::new(user_id, device_id, TOKEN_TYPE_ACCESS, access_token_ttl());
+    encode_claims(&claims, &jwt_secret()?)
+}
+
+pub fn verify_access_token(token: &str) -> Result<Claims, TokenError> {
+    decode_claims(token, &jwt_secret()?, TOKEN_TYPE_ACCESS)
+}
+
+/// 返回 token 以及其 claims，调用方需要用 jti 落库以支持轮换和吊销
+pub fn issue_refresh_token(
+    user_id: &str,
+    device_id: Option<&str>,
+) -> Result<(String, Claims), TokenError> {
+    let claims = Claims::new(user_id, device_id, TOKEN_TYPE_REFRESH, refresh_token_ttl());
+    let token = encode_claims(&claims, &jwt_secret()?)?;
+    Ok((token, claims))
+}
+
+pub fn verify_refresh_token(token: &str) -> Result<Claims, TokenError> {
+    decode_claims(token, &jwt_secret()?, TOKEN_TYPE_REFRESH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    #[test]
+    fn test_round_trip() {
+        let claims = Claims::new("user", Some("dev"), TOKEN_TYPE_ACCESS, 60);
+        let token = encode_claims(&claims, SECRET).unwrap();
+        let decoded = decode_claims(&token, SECRET, TOKEN_TYPE_ACCESS).unwrap();
+        assert_eq!(decoded.sub, "user");
+        assert_eq!(decoded.dev.as_deref(), Some("dev"));
+    }
+
+    #[test]
+    fn test_expired_token() {
+        let claims = Claims::new("user", None, TOKEN_TYPE_ACCESS, -3600);
+        let token = encode_claims(&claims, SECRET).unwrap();
+        let err = decode_claims(&token, SECRET, TOKEN_TYPE_ACCESS).unwrap_err();
+        assert_eq!(err.reason(), "token_expired");
+    }
+
+    #[test]
+    fn test_wrong_secret_and_type() {
+        let claims = Claims::new("user", None, TOKEN_TYPE_ACCESS, 60);
+        let token = encode_claims(&claims, SECRET).unwrap();
+        assert!(decode_claims(&token, "other", TOKEN_TYPE_ACCESS).is_err());
+        assert!(matches!(
+            decode_claims(&token, SECRET, TOKEN_TYPE_REFRESH),
+            Err(TokenError::WrongType(_))
+        ));
+    }
+}
@@ -1,4 +1,6 @@
+pub mod jwt;
 pub mod mqtt;
+pub mod rate_limit;
 use crate::models::establish_connection;
 use crate::models::role::Role;
 use crate::models::schema;
@@ -21,6 +23,14 @@ pub fn insert_default_role() {
         audition_url: "".to_string(),
         created_at: SystemTime::now(),
         updated_at: SystemTime::now(),
+        model: None,
+        temperature: None,
+        top_p: None,
+        max_tokens: None,
+        stop_sequences: None,
+        presence_penalty: None,
+        frequency_penalty: None,
+        context_token_budget: None,
     };
 
     match diesel::insert_into(schema::roles::table)
@@ -33,6 +43,23 @@ pub fn insert_default_role() {
     }
 }
 
+/// 时间戳 (秒)，早于 1970 年时为 0
+pub fn to_unix_secs(time: SystemTime) -> i64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
 pub fn gen_new_id() -> String {
     xid::new().to_string()
 }
+
+/// 随机密钥，用于设备凭证等需要保密的场景
+pub fn gen_secret() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// 6 位数字配对码
+pub fn gen_pairing_code() -> String {
+    format!("{:06}", uuid::Uuid::new_v4().as_u128() % 1_000_000)
+}
@@ -3,6 +3,7 @@ use crate::config::OZ_SERVER_CONFIG;
 use base64::{Engine as _, engine::general_purpose::STANDARD};
 use crate::json::mqtt::{MqttEvent, Payload, MessagePayload, MqttMessage};
 use crate::constant::{MQTT_MSG_SOURCE_USER, MQTT_MSG_SOURCE_DEVICE};
+use crate::services::metrics::record_mqtt_publish;
 
 
 async fn get_auth() -> Result<String, anyhow::Error> {
@@ -12,10 +13,16 @@ async fn get_auth() -> Result<String, anyhow::Error> {
 }
 
 pub async fn publish_event(event: String, device_id: String) -> Result<(), anyhow::Error> {
+    let result = send_event(event, device_id).await;
+    record_mqtt_publish("event", &result);
+    result
+}
+
+async fn send_event(event: String, device_id: String) -> Result<(), anyhow::Error> {
     let client = Client::new();
 
     let payload = Payload {
-        event: event,
+        event,
     };
 
     let mqtt_event = MqttEvent {
@@ -36,7 +43,13 @@ pub async fn publish_event(event: String, device_id: String) -> Result<(), anyho
     Ok(())
 }
 
-pub async fn publish_message(self_message: String, device_message: String, device_id: String) -> Result<(), anyhow::Error> {
+pub async fn publish_message(self_message: String, device_message: String, user_id: String) -> Result<(), anyhow::Error> {
+    let result = send_message(self_message, device_message, user_id).await;
+    record_mqtt_publish("chat", &result);
+    result
+}
+
+async fn send_message(self_message: String, device_message: String, user_id: String) -> Result<(), anyhow::Error> {
 
     let payload = MessagePayload {
         source: MQTT_MSG_SOURCE_USER.to_string(),
@@ -52,7 +65,7 @@ pub async fn publish_message(self_message: String, device_message: String, devic
 
     let mqtt_message = MqttMessage {
         payload: serde_json::to_string(&payload)?,
-        topic: format!("app/{}/chat", device_id),
+        topic: format!("app/{}/chat", user_id),
     };
 
     let client = Client::new();
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// 按 key 统计失败次数，时间窗口内失败太多就锁定到窗口结束
+pub struct FailureLimiter {
+    max_failures: u32,
+    window: Duration,
+    failures: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+impl FailureLimiter {
+    pub fn new(max_failures: u32, window: Duration) -> Self {
+        Self {
+            max_failures,
+            window,
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_locked(&self, key: &str) -> bool {
+        self.is_locked_at(key, Instant::now())
+    }
+
+    pub fn record_failure(&self, key: &str) {
+        self.record_failure_at(key, Instant::now());
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.failures.lock().unwrap().remove(key);
+    }
+
+    fn is_locked_at(&self, key: &str, now: Instant) -> bool {
+        let mut failures = self.failures.lock().unwrap();
+        match failures.get(key) {
+            Some((_, start)) if now.duration_since(*start) >= self.window => {
+                failures.remove(key);
+                false
+            }
+            Some((count, _)) => *count >= self.max_failures,
+            None => false,
+        }
+    }
+
+    fn record_failure_at(&self, key: &str, now: Instant) {
+        let mut failures = self.failures.lock().unwrap();
+        // 顺便清掉过期的记录，避免一直增长
+        failures.retain(|_, (_, start)| now.duration_since(*start) < self.window);
+        failures.entry(key.to_string()).or_insert((0, now)).0 += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_after_max_failures() {
+        let limiter = FailureLimiter::new(3, Duration::from_secs(60));
+        let start = Instant::now();
+        for _ in 0..2 {
+            limiter.record_failure_at("u1", start);
+        }
+        assert!(!limiter.is_locked_at("u1", start));
+        limiter.record_failure_at("u1", start);
+        assert!(limiter.is_locked_at("u1", start));
+        // 其他用户不受影响
+        assert!(!limiter.is_locked_at("u2", start));
+        // 窗口结束后解锁
+        assert!(!limiter.is_locked_at("u1", start + Duration::from_secs(60)));
+
+        limiter.record_failure_at("u2", start);
+        limiter.reset("u2");
+        assert!(limiter.failures.lock().unwrap().get("u2").is_none());
+    }
+}
@@ -1,7 +1,7 @@
 use oz_server::utils::insert_default_role;
-use oz_server::handlers::chat;
 
 #[tokio::test]
+#[ignore = "needs .config.json and the database from database_url"]
 async fn test_model() {
     insert_default_role();
 }